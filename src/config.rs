@@ -0,0 +1,249 @@
+//! Config file loaded at startup, every field is optional and missing ones keep default
+//!
+//! ```toml
+//! scrollback = 20000
+//!
+//! [font]
+//! path = "/usr/share/fonts/TTF/FiraCode-Regular.ttf"
+//! size = 13.0
+//!
+//! [shell]
+//! program = "/bin/zsh"
+//! args = ["-l"]
+//!
+//! [theme]
+//! background = "#1d1f21"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use termwiz::color::RgbColor;
+
+use crate::{
+    render::{MAX_FONT_SIZE, MIN_FONT_SIZE},
+    AppConfig, Bell, CursorShape, Osc52, PasteNewline, ScrollbarPolicy, Theme,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "Failed to read config: {}", err),
+            ConfigError::Parse(err) => write!(f, "Invalid config: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub font: FontConfig,
+    pub window: WindowConfig,
+    pub cursor: CursorConfig,
+    pub shell: ShellConfig,
+    pub theme: Option<Theme>,
+    /// Lines kept in scrollback
+    pub scrollback: Option<usize>,
+    pub bell: Option<Bell>,
+    /// Whether applications can access clipboard with OSC 52
+    pub osc52: Option<Osc52>,
+    pub copy_on_select: Option<bool>,
+    pub paste_newline: Option<PasteNewline>,
+    /// `#rrggbb`
+    pub selection_color: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FontConfig {
+    /// Monospace font file, embedded font is used when it's missing
+    pub path: Option<PathBuf>,
+    /// Fonts for missing glyphs like CJK, in order
+    pub fallbacks: Vec<PathBuf>,
+    /// Points
+    pub size: Option<f32>,
+    pub ligatures: Option<bool>,
+    pub bold_is_bright: Option<bool>,
+    /// Multiplier of font height
+    pub line_height: Option<f32>,
+    /// Logical pixels added to each side of cell horizontally
+    pub cell_padding: Option<f32>,
+    pub contrast: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    /// Grid size of initial window
+    pub columns: Option<u32>,
+    pub rows: Option<u32>,
+    /// `#rrggbb`
+    pub background: Option<String>,
+    /// Alpha of background, below 1.0 shows through transparent window
+    pub opacity: Option<f32>,
+    /// MSAA sample count
+    pub sample_count: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub scrollbar: Option<ScrollbarPolicy>,
+    pub always_on_top: Option<bool>,
+    /// Logical pixels between window edge and grid
+    pub padding: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CursorConfig {
+    pub shape: Option<CursorShape>,
+    pub blink: Option<bool>,
+    /// `#rrggbb`
+    pub color: Option<String>,
+    /// Draw character under block cursor with its background color
+    pub inverse: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Default to `$SHELL` on unix, `powershell` on windows
+    pub program: Option<String>,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// `$XDG_CONFIG_HOME/temu/config.toml`, `%APPDATA%\temu\config.toml` on windows
+    pub fn default_path() -> Option<PathBuf> {
+        #[cfg(unix)]
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        #[cfg(windows)]
+        let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+
+        dir.map(|dir| dir.join("temu").join("config.toml"))
+    }
+
+    pub fn into_app_config(self) -> AppConfig {
+        let mut app = AppConfig::default();
+        let render = &mut app.render;
+
+        let Self {
+            font,
+            window,
+            cursor,
+            shell,
+            theme,
+            scrollback,
+            bell,
+            osc52,
+            copy_on_select,
+            paste_newline,
+            selection_color,
+        } = self;
+
+        app.font_path = font.path;
+        app.fallback_font_paths = font.fallbacks;
+        set(
+            &mut render.font_size,
+            font.size
+                .map(|size| size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE)),
+        );
+        set(&mut render.ligatures, font.ligatures);
+        set(&mut render.bold_is_bright, font.bold_is_bright);
+        set(&mut render.line_height, font.line_height);
+        set(&mut render.cell_padding, font.cell_padding);
+        set(&mut render.text_contrast, font.contrast);
+
+        set(&mut app.columns, window.columns);
+        set(&mut app.rows, window.rows);
+        if let Some([r, g, b]) = window.background.as_deref().and_then(parse_color) {
+            render.background = [r, g, b, render.background[3]];
+        }
+        set(
+            &mut render.background[3],
+            window.opacity.map(|a| a.clamp(0.0, 1.0)),
+        );
+        set(&mut render.sample_count, window.sample_count);
+        set(&mut render.max_fps, window.max_fps);
+        set(&mut render.scrollbar_policy, window.scrollbar);
+        set(&mut app.always_on_top, window.always_on_top);
+        set(
+            &mut render.window_padding,
+            window.padding.map(|p| p.max(0.0)),
+        );
+
+        set(&mut render.cursor_shape, cursor.shape);
+        set(&mut render.cursor_blink, cursor.blink);
+        if let Some([r, g, b]) = cursor.color.as_deref().and_then(parse_color) {
+            render.cursor_color = [r, g, b, 1.0];
+        }
+        set(&mut render.cursor_inverse, cursor.inverse);
+
+        app.pty.shell = shell.program;
+        app.pty.args = shell.args;
+        app.pty.cwd = shell.cwd;
+        app.pty.env = shell.env.into_iter().collect();
+
+        app.theme = theme;
+        set(&mut render.scrollback_size, scrollback);
+        set(&mut render.bell, bell);
+        set(&mut render.allow_osc52, osc52);
+        set(&mut render.copy_on_select, copy_on_select);
+        set(&mut render.paste_newline, paste_newline);
+        if let Some([r, g, b]) = selection_color.as_deref().and_then(parse_color) {
+            render.selection_color = [r, g, b, 1.0];
+        }
+
+        app
+    }
+}
+
+fn set<T>(slot: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *slot = value;
+    }
+}
+
+fn parse_color(color: &str) -> Option<[f32; 3]> {
+    match RgbColor::from_rgb_str(color) {
+        Some(rgb) => {
+            let (r, g, b, _) = rgb.to_tuple_rgba();
+            Some([r, g, b])
+        }
+        None => {
+            log::warn!("Invalid config color: {}", color);
+            None
+        }
+    }
+}
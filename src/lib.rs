@@ -0,0 +1,420 @@
+//! Terminal emulator core which can be embedded in any window
+//!
+//! `temu` binary is a thin wrapper which drives [`TemuApp`] with native window of `temu-window`
+
+mod config;
+mod render;
+mod term;
+
+use std::{
+    io::{BufReader, Read, Write},
+    path::PathBuf,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use portable_pty::{Child, MasterPty};
+use raw_window_handle::HasRawWindowHandle;
+use termwiz::escape::{parser::Parser, Action};
+
+pub use self::{
+    config::{Config, ConfigError},
+    render::{
+        Bell, CursorShape, Osc52, PasteNewline, RenderConfig, ScrollbarPolicy, Selection,
+        SelectionMode, FONT_SIZE,
+    },
+    term::{PtyConfig, Theme},
+};
+/// Events exchanged with window, `temu-window` is the only definition
+pub use temu_window::{Key, Modifiers, TemuEvent, WindowConfig};
+
+const COLUMN: u32 = 80;
+const ROW: u32 = 23;
+const DEFAULT_BG: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const DEFAULT_TEXT: [f32; 3] = [1.0, 1.0, 1.0];
+/// How long reaping waits for reader thread after shell is killed
+const READER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Everything [`TemuApp`] needs before window is created
+#[derive(Clone, Debug)]
+pub struct AppConfig {
+    pub render: RenderConfig,
+    pub pty: PtyConfig,
+    pub theme: Option<Theme>,
+    /// Monospace font file to use instead of embedded one
+    pub font_path: Option<PathBuf>,
+    /// Fonts for missing glyphs like CJK
+    pub fallback_font_paths: Vec<PathBuf>,
+    /// Grid size of initial window, grid follows window size after
+    pub columns: u32,
+    pub rows: u32,
+    /// Keep window above others
+    pub always_on_top: bool,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            render: RenderConfig::default(),
+            pty: PtyConfig::default(),
+            theme: None,
+            font_path: None,
+            fallback_font_paths: Vec::new(),
+            columns: COLUMN,
+            rows: ROW,
+            always_on_top: false,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Initial grid, at least single cell
+    fn grid_size(&self) -> (usize, usize) {
+        (self.columns.max(1) as usize, self.rows.max(1) as usize)
+    }
+}
+
+struct Pty {
+    output: Box<dyn Write + Send>,
+    master: Box<dyn MasterPty + Send>,
+    shell: Box<dyn Child + Send + Sync>,
+    msg_rx: Receiver<Vec<Action>>,
+    reader_handle: ReaderHandle,
+}
+
+/// Terminal which is not attached to window yet
+pub struct TemuApp {
+    config: AppConfig,
+    faces: render::FontFaces,
+    adapter_handle: JoinHandle<(wgpu::Instance, Vec<wgpu::Adapter>)>,
+    pty_handle: JoinHandle<Pty>,
+}
+
+impl TemuApp {
+    /// Start enumerating adapters and spawning shell, so they overlap with window creation
+    pub fn new(config: AppConfig) -> Self {
+        let adapter_handle = std::thread::spawn(|| {
+            profiling::register_thread!("Init Adapter Thread");
+            let instance = wgpu::Instance::new(wgpu::Backends::all());
+            let adapters: Vec<_> = instance.enumerate_adapters(wgpu::Backends::all()).collect();
+
+            (instance, adapters)
+        });
+
+        let pty_config = config.pty.clone();
+        let grid_size = config.grid_size();
+        let pty_handle = std::thread::spawn(move || {
+            profiling::register_thread!("Init Pty Thread");
+            let (master, shell) = crate::term::start_pty(&pty_config, grid_size);
+            let input = master.try_clone_reader().unwrap();
+
+            let (msg_rx, reader_handle) = run_reader(input);
+            let output = master.try_clone_writer().unwrap();
+
+            Pty {
+                output,
+                master,
+                shell,
+                msg_rx,
+                reader_handle,
+            }
+        });
+
+        // metrics are needed for window size, rasterizing waits until scale factor is known
+        let faces =
+            render::load_font_faces(config.font_path.as_deref(), &config.fallback_font_paths);
+
+        Self {
+            config,
+            faces,
+            adapter_handle,
+            pty_handle,
+        }
+    }
+
+    /// Window fits configured grid and it's transparent only when background has alpha
+    ///
+    /// Minimum size fits single cell
+    pub fn window_config(&self) -> WindowConfig {
+        let size = |cols, rows| {
+            let config = &self.config.render;
+            render::grid_window_size(&self.faces, config.font_size, config, cols, rows)
+        };
+        let (cols, rows) = self.config.grid_size();
+        let [width, height] = size(cols as u32, rows as u32);
+        let [min_width, min_height] = size(1, 1);
+
+        WindowConfig {
+            width,
+            height,
+            min_width,
+            min_height,
+            transparent: self.config.render.background[3] < 1.0,
+            always_on_top: self.config.always_on_top,
+        }
+    }
+
+    /// Start renderer thread drawing to window of `handle`
+    ///
+    /// Window events are read from `event_rx` and requests to window like [`TemuEvent::SetTitle`]
+    /// are sent to `window_tx`, [`TemuEvent::Exit`] is sent last after shell is reaped.
+    /// `event_tx` should be sender of `event_rx`, it's used to stop renderer.
+    pub fn spawn<H: HasRawWindowHandle + Send + 'static>(
+        self,
+        handle: H,
+        size: (u32, u32),
+        scale_factor: f32,
+        event_tx: Sender<TemuEvent>,
+        event_rx: Receiver<TemuEvent>,
+        window_tx: Sender<TemuEvent>,
+    ) -> TemuHandle {
+        self.spawn_inner(
+            Some(Box::new(handle)),
+            size,
+            scale_factor,
+            event_tx,
+            event_rx,
+            window_tx,
+        )
+    }
+
+    /// Start renderer thread without window surface, nothing is presented
+    ///
+    /// Grid is drawn only by [`TemuHandle::screenshot`], so it can run where there is no display
+    /// like tests with `MockWindow` of `temu-window`.
+    pub fn spawn_headless(
+        self,
+        size: (u32, u32),
+        scale_factor: f32,
+        event_tx: Sender<TemuEvent>,
+        event_rx: Receiver<TemuEvent>,
+        window_tx: Sender<TemuEvent>,
+    ) -> TemuHandle {
+        self.spawn_inner(None, size, scale_factor, event_tx, event_rx, window_tx)
+    }
+
+    fn spawn_inner(
+        self,
+        handle: Option<Box<dyn HasRawWindowHandle + Send>>,
+        (width, height): (u32, u32),
+        scale_factor: f32,
+        event_tx: Sender<TemuEvent>,
+        event_rx: Receiver<TemuEvent>,
+        window_tx: Sender<TemuEvent>,
+    ) -> TemuHandle {
+        let Self {
+            config,
+            faces,
+            adapter_handle,
+            pty_handle,
+        } = self;
+        let grid_size = config.grid_size();
+        let AppConfig {
+            render: render_config,
+            theme,
+            ..
+        } = config;
+
+        let font_texture_handle = std::thread::spawn(move || {
+            profiling::register_thread!("Init FontTexture Thread");
+
+            // device is not ready yet, CellContext shrinks atlas when it's over the limit
+            render::generate_font_texture(faces, render_config.font_size, scale_factor, u32::MAX)
+        });
+
+        let thread = std::thread::spawn(move || {
+            let (instance, adapters) = adapter_handle.join().unwrap();
+            // handle is kept until renderer ends since surface refers to its window
+            let surface = handle
+                .as_ref()
+                .map(|handle| unsafe { instance.create_surface(&&**handle) });
+
+            let Pty {
+                output,
+                master,
+                shell,
+                msg_rx,
+                reader_handle,
+            } = pty_handle.join().unwrap();
+
+            let adapter = match select_adapter(&instance, adapters, surface.as_ref()) {
+                Some(adapter) => adapter,
+                None => {
+                    log::error!("Failed to find an adapter which supports window surface");
+                    // there is no console with windows subsystem, show it in title instead
+                    window_tx
+                        .send(TemuEvent::SetTitle(
+                            "Temu - No supported GPU adapter found".into(),
+                        ))
+                        .ok();
+                    drop((output, master));
+                    reap_shell(shell, reader_handle);
+                    return;
+                }
+            };
+
+            let font_texture = font_texture_handle.join().unwrap();
+            let exit_tx = window_tx.clone();
+
+            render::run(
+                surface,
+                adapter,
+                font_texture,
+                width,
+                height,
+                grid_size,
+                scale_factor,
+                render_config,
+                theme,
+                event_rx,
+                window_tx,
+                msg_rx,
+                output,
+                master,
+            );
+
+            reap_shell(shell, reader_handle);
+
+            exit_tx.send(TemuEvent::Exit).ok();
+        });
+
+        TemuHandle { event_tx, thread }
+    }
+}
+
+/// Running renderer of [`TemuApp`]
+pub struct TemuHandle {
+    event_tx: Sender<TemuEvent>,
+    thread: JoinHandle<()>,
+}
+
+impl TemuHandle {
+    /// Close terminal as if window is closed and wait until shell is reaped
+    pub fn stop(self) {
+        self.event_tx.send(TemuEvent::Close).ok();
+        self.join();
+    }
+
+    /// Resize window to fit `cols` x `rows` grid with current cell size
+    pub fn request_resize(&self, cols: u32, rows: u32) {
+        self.event_tx
+            .send(TemuEvent::ResizeGrid { cols, rows })
+            .ok();
+    }
+
+    /// Replace regular font with font file and relayout grid, fallback fonts are kept
+    pub fn reload_font(&self, path: impl Into<PathBuf>) {
+        self.event_tx.send(TemuEvent::ReloadFont(path.into())).ok();
+    }
+
+    /// Save current grid as PNG to `path`
+    pub fn screenshot(&self, path: impl Into<PathBuf>) {
+        self.event_tx
+            .send(TemuEvent::Screenshot(Some(path.into())))
+            .ok();
+    }
+
+    /// Wait until renderer exits by [`TemuEvent::Close`] or end of shell
+    pub fn join(self) {
+        self.thread.join().ok();
+    }
+}
+
+/// Prefer enumerated adapter which can present to surface, then software one
+///
+/// Any adapter can be used without surface
+fn select_adapter(
+    instance: &wgpu::Instance,
+    adapters: Vec<wgpu::Adapter>,
+    surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+    for adapter in adapters.iter() {
+        log::info!("Found adapter: {:?}", adapter.get_info());
+    }
+
+    if let Some(adapter) = adapters
+        .into_iter()
+        .find(|a| surface.map_or(true, |surface| a.is_surface_supported(surface)))
+    {
+        return Some(adapter);
+    }
+
+    log::warn!("No adapter supports window surface, try fallback adapter");
+    futures_executor::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: true,
+        compatible_surface: surface,
+    }))
+}
+
+/// Reader thread of pty
+struct ReaderHandle {
+    thread: JoinHandle<()>,
+    /// disconnected when thread ends
+    done_rx: Receiver<()>,
+}
+
+/// Master pty should be dropped before this so reader gets EOF
+///
+/// Reader is detached when it doesn't end in [`READER_TIMEOUT`],
+/// e.g. background process of shell still holds pty open
+fn reap_shell(mut shell: Box<dyn Child + Send + Sync>, reader_handle: ReaderHandle) {
+    // shell may be still alive when window is closed
+    if let Err(err) = shell.kill() {
+        log::debug!("Failed to kill shell: {}", err);
+    }
+    match shell.wait() {
+        Ok(status) => log::info!("Shell exited: {:?}", status),
+        Err(err) => log::error!("Failed to wait shell: {}", err),
+    }
+    match reader_handle.done_rx.recv_timeout(READER_TIMEOUT) {
+        Err(RecvTimeoutError::Disconnected) => {
+            reader_handle.thread.join().ok();
+        }
+        Ok(()) | Err(RecvTimeoutError::Timeout) => {
+            log::warn!("Reader thread didn't end, detach it");
+        }
+    }
+}
+
+fn run_reader(input: Box<dyn Read + Send>) -> (Receiver<Vec<Action>>, ReaderHandle) {
+    let (tx, rx) = crossbeam_channel::bounded(512);
+    let (done_tx, done_rx) = crossbeam_channel::bounded(0);
+
+    let thread = std::thread::spawn(move || {
+        profiling::register_thread!("Reader Thread");
+        // dropped on every return
+        let _done_tx: Sender<()> = done_tx;
+        let mut parser = Parser::new();
+        let mut reader = BufReader::new(input);
+        let mut buf = [0; 8196];
+
+        loop {
+            profiling::scope!("Read");
+            match reader.read(&mut buf) {
+                Ok(0) => {
+                    log::info!("pty input ended");
+                    return;
+                }
+                Ok(len) => {
+                    profiling::scope!("Parse");
+                    let actions = parser.parse_as_vec(&buf[..len]);
+                    if tx.send(actions).is_err() {
+                        // renderer is closed
+                        return;
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                    continue;
+                }
+                Err(err) => {
+                    log::error!("IO error: {}", err);
+                    return;
+                }
+            }
+        }
+    });
+
+    (rx, ReaderHandle { thread, done_rx })
+}
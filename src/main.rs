@@ -1,116 +1,74 @@
 #![windows_subsystem = "windows"]
 
-mod render;
-mod term;
+use std::path::PathBuf;
 
-use std::io::{BufReader, Read};
-
-use crossbeam_channel::Receiver;
+use temu::{Config, ConfigError, TemuApp};
 use temu_window::{init_native_window, TemuWindow};
-use termwiz::escape::{parser::Parser, Action};
-
-const COLUMN: u32 = 80;
-const ROW: u32 = 23;
-const DEFAULT_BG: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-const DEFAULT_TEXT: [f32; 3] = [1.0, 1.0, 1.0];
 
 fn main() {
     profiling::register_thread!("Main Thread");
+    // font is loaded by TemuApp::new
+    env_logger::init();
 
-    let adapter_handle = std::thread::spawn(|| {
-        profiling::register_thread!("Init Adapter Thread");
-        let instance = wgpu::Instance::new(wgpu::Backends::all());
-        let adapters: Vec<_> = instance.enumerate_adapters(wgpu::Backends::all()).collect();
-
-        (instance, adapters)
-    });
-
-    let pty_handle = std::thread::spawn(|| {
-        profiling::register_thread!("Init Pty Thread");
-        let (master, shell) = crate::term::start_pty();
-        let input = master.try_clone_reader().unwrap();
-
-        let msg_rx = run_reader(input);
-        let output = master.try_clone_writer().unwrap();
-
-        (output, master, shell, msg_rx)
-    });
+    let mut config = load_config().into_app_config();
+    // monospace font file to use instead of configured one
+    if let Some(path) = std::env::var_os("TEMU_FONT") {
+        config.font_path = Some(PathBuf::from(path));
+    }
+    // fonts for missing glyphs like CJK, separated like PATH
+    if let Some(paths) = std::env::var_os("TEMU_FALLBACK_FONTS") {
+        config.fallback_font_paths = std::env::split_paths(&paths).collect();
+    }
+    let app = TemuApp::new(config);
 
     let (event_tx, event_rx) = crossbeam_channel::bounded(64);
-
-    env_logger::init();
+    let (window_tx, window_rx) = crossbeam_channel::bounded(64);
 
     log::info!("Init window");
-    let window = init_native_window(event_tx.clone());
-    let scale_factor = window.scale_factor();
-    let font_texture_handle = std::thread::spawn(move || {
-        profiling::register_thread!("Init FontTexture Thread");
-
-        render::generate_font_texture(scale_factor)
-    });
-    let handle = window.get_raw_event_handle();
-    let (width, height) = window.size();
-
-    std::thread::spawn(move || {
-        let (instance, adapters) = adapter_handle.join().unwrap();
-        let surface = unsafe { instance.create_surface(&handle) };
-
-        let adapter = adapters
-            .into_iter()
-            .find(|a| a.is_surface_supported(&surface))
-            .expect("Failed to find an appropriate adapter");
-
-        let (output, _master, _shell, msg_rx) = pty_handle.join().unwrap();
-        let font_texture = font_texture_handle.join().unwrap();
-
-        render::run(
-            surface,
-            adapter,
-            font_texture,
-            width,
-            height,
-            scale_factor,
-            event_rx,
-            msg_rx,
-            output,
-        );
-    });
+    let window = init_native_window(event_tx.clone(), window_rx, app.window_config());
+    let handle = app.spawn(
+        window.get_raw_event_handle(),
+        window.size(),
+        window.scale_factor(),
+        event_tx,
+        event_rx,
+        window_tx,
+    );
 
     log::info!("Start window");
     window.run();
+    // some platforms return from run before renderer cleans up
+    handle.join();
 }
 
-fn run_reader(input: Box<dyn Read + Send>) -> Receiver<Vec<Action>> {
-    let (tx, rx) = crossbeam_channel::bounded(512);
-
-    std::thread::spawn(move || {
-        profiling::register_thread!("Reader Thread");
-        let mut parser = Parser::new();
-        let mut reader = BufReader::new(input);
-        let mut buf = [0; 8196];
-
-        loop {
-            profiling::scope!("Read");
-            match reader.read(&mut buf) {
-                Ok(0) => {
-                    log::info!("pty input ended");
-                    return;
-                }
-                Ok(len) => {
-                    profiling::scope!("Parse");
-                    let actions = parser.parse_as_vec(&buf[..len]);
-                    tx.send(actions).unwrap();
-                }
-                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {
-                    continue;
-                }
-                Err(err) => {
-                    log::error!("IO error: {}", err);
-                    return;
-                }
-            }
+/// `--config <path>` overrides default path, missing file at default path is not an error
+fn load_config() -> Config {
+    let mut args = std::env::args_os().skip(1);
+    let explicit = loop {
+        match args.next() {
+            Some(arg) if arg == "--config" => break args.next().map(PathBuf::from),
+            Some(_) => continue,
+            None => break None,
         }
-    });
-
-    rx
+    };
+    let path = match explicit.clone().or_else(Config::default_path) {
+        Some(path) => path,
+        None => return Config::default(),
+    };
+
+    match Config::load(&path) {
+        Ok(config) => {
+            log::info!("Loaded config from {}", path.display());
+            config
+        }
+        Err(ConfigError::Io(err))
+            if explicit.is_none() && err.kind() == std::io::ErrorKind::NotFound =>
+        {
+            Config::default()
+        }
+        Err(err) => {
+            log::error!("{}: {}", path.display(), err);
+            Config::default()
+        }
+    }
 }
@@ -1,26 +1,206 @@
 mod atlas;
 mod cell;
 mod font_texture;
+mod inline_image;
+mod selection;
 mod viewport;
 
-use std::{io::Write, sync::Arc, time::Instant};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-pub use self::viewport::Viewport;
 use self::{
     cell::CellContext,
-    font_texture::{FontTexture, GlyphCacheInfo},
+    font_texture::{FontCache, FontTexture, GlyphCacheInfo},
+};
+pub use self::{
+    cell::{grid_window_size, CursorShape, ScrollbarPolicy},
+    font_texture::FontFaces,
+    selection::{Selection, SelectionMode},
+    viewport::{OffscreenTarget, Viewport},
 };
-use crossbeam_channel::Receiver;
+use crate::term::Theme;
+use crossbeam_channel::{Receiver, Sender};
 use futures_executor::block_on;
-use temu_window::TemuEvent;
-use termwiz::escape::Action;
-use wezterm_term::{KeyCode, Terminal, TerminalSize};
+use portable_pty::{MasterPty, PtySize};
+use serde::Deserialize;
+use temu_window::{Key, Modifiers, TemuEvent};
+use termwiz::escape::{
+    csi::{DecPrivateMode, DecPrivateModeCode, Mode},
+    osc::ITermProprietary,
+    Action, ControlCode, Esc, EscCode, OperatingSystemCommand, CSI,
+};
+use wezterm_term::{KeyCode, KeyModifiers, Terminal, TerminalSize};
 
 const FONT: &[u8] = include_bytes!("../Hack Regular Nerd Font Complete Mono.ttf");
 
-const FONT_SIZE: f32 = 15.0;
-const TEXTURE_WIDTH: u32 = 1024;
-const TEXTURE_SIZE: usize = (TEXTURE_WIDTH * TEXTURE_WIDTH) as usize;
+pub const FONT_SIZE: f32 = 15.0;
+pub const MIN_FONT_SIZE: f32 = 6.0;
+pub const MAX_FONT_SIZE: f32 = 72.0;
+/// Bounds of glyph atlas layer width, actual width depends on font size
+const MIN_TEXTURE_WIDTH: u32 = 1024;
+const MAX_TEXTURE_WIDTH: u32 = 8192;
+/// How long visual bell flashes
+const BELL_DURATION: Duration = Duration::from_millis(100);
+const BELL_FLASH: wgpu::Color = wgpu::Color {
+    r: 0.4,
+    g: 0.4,
+    b: 0.4,
+    a: 1.0,
+};
+/// Columns panned by one notch of shift + wheel
+const PAN_COLUMNS: isize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderConfig {
+    /// MSAA sample count, falls back to 4x or 1x when adapter can't use it
+    pub sample_count: u32,
+    /// Shape whole runs so ligature glyphs can span multiple cells
+    ///
+    /// When false `liga`, `clig` and `calt` are disabled so `==` and `->` stay separate
+    pub ligatures: bool,
+    /// Draw bold text with ANSI color 0-7 in bright color 8-15, bold font is still used
+    pub bold_is_bright: bool,
+    /// Cursor shape when application doesn't request one with DECSCUSR
+    pub cursor_shape: CursorShape,
+    /// Blink cursor when application doesn't request steady one
+    pub cursor_blink: bool,
+    /// Cursor color when application doesn't set one with OSC 12
+    pub cursor_color: [f32; 4],
+    /// Draw character under block cursor with its background color so it stays readable
+    pub cursor_inverse: bool,
+    pub blink_interval: Duration,
+    /// `Fifo` caps to vsync, unsupported mode falls back to `Fifo`
+    pub present_mode: wgpu::PresentMode,
+    /// Window background, also used as terminal default background
+    ///
+    /// Alpha below 1.0 shows through transparent window
+    pub background: [f32; 4],
+    /// Lines kept in scrollback
+    pub scrollback_size: usize,
+    /// Whether applications can access clipboard with OSC 52
+    pub allow_osc52: Osc52,
+    /// Background of selected cells
+    pub selection_color: [f32; 4],
+    /// Multiplier of font height, e.g. 1.2 for looser lines
+    pub line_height: f32,
+    /// Logical pixels added to each side of cell horizontally
+    pub cell_padding: f32,
+    /// Logical pixels between window edge and grid
+    pub window_padding: f32,
+    pub bell: Bell,
+    /// Copy selected text to primary selection when dragging ends
+    ///
+    /// Middle click pastes it. Platforms without primary selection copy nothing and middle click
+    /// pastes clipboard instead
+    pub copy_on_select: bool,
+    pub scrollbar_policy: ScrollbarPolicy,
+    /// What to do with multi-line paste when application doesn't enable bracketed paste
+    pub paste_newline: PasteNewline,
+    /// Max rate of redraw caused by pty output, intermediate output is not drawn
+    pub max_fps: u32,
+    /// Thickens glyph edges, 0.0 keeps coverage as rasterized
+    pub text_contrast: f32,
+    /// Font size in points, Ctrl+Shift+0 restores it
+    pub font_size: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteNewline {
+    /// Send as is, each line runs as command
+    Allow,
+    /// Join lines with space so nothing runs until user presses enter
+    Join,
+    /// Hold paste until same text is pasted again
+    Confirm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bell {
+    Disabled,
+    /// Flash window background
+    Visual,
+    /// Let window system notify user
+    Audible,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Osc52 {
+    Disabled,
+    /// Applications can set clipboard but can't read it
+    WriteOnly,
+    ReadWrite,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            sample_count: 1,
+            ligatures: true,
+            bold_is_bright: false,
+            cursor_shape: CursorShape::Block,
+            cursor_blink: true,
+            cursor_color: [1.0; 4],
+            cursor_inverse: true,
+            blink_interval: Duration::from_millis(500),
+            present_mode: wgpu::PresentMode::Mailbox,
+            background: crate::DEFAULT_BG,
+            scrollback_size: 10000,
+            allow_osc52: Osc52::WriteOnly,
+            selection_color: [0.25, 0.35, 0.55, 1.0],
+            line_height: 1.0,
+            cell_padding: 0.0,
+            window_padding: 0.0,
+            bell: Bell::Visual,
+            copy_on_select: true,
+            scrollbar_policy: ScrollbarPolicy::Auto,
+            paste_newline: PasteNewline::Confirm,
+            max_fps: 120,
+            text_contrast: 0.5,
+            font_size: FONT_SIZE,
+        }
+    }
+}
+
+impl RenderConfig {
+    /// Sample count which `adapter` can render `format` with
+    ///
+    /// wgpu doesn't report sample counts of format, it only guarantees 1x and 4x for formats
+    /// which can be render attachment. Other counts fall back to 4x.
+    fn supported_sample_count(&self, adapter: &wgpu::Adapter, format: wgpu::TextureFormat) -> u32 {
+        let renderable = adapter
+            .get_texture_format_features(format)
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+
+        match self.sample_count {
+            0 | 1 => 1,
+            n if !renderable => {
+                log::warn!(
+                    "{:?} can't be multisampled, MSAA {}x is disabled",
+                    format,
+                    n
+                );
+                1
+            }
+            4 => 4,
+            n => {
+                log::warn!(
+                    "MSAA {}x is not guaranteed on {}, fall back to 4x",
+                    n,
+                    adapter.get_info().name
+                );
+                4
+            }
+        }
+    }
+}
 
 #[allow(unused)]
 pub struct WgpuContext {
@@ -28,7 +208,13 @@ pub struct WgpuContext {
     device: wgpu::Device,
     queue: wgpu::Queue,
     cell_ctx: CellContext,
+    sample_count: u32,
+    msaa_view: Option<wgpu::TextureView>,
+    clear_color: wgpu::Color,
+    /// visual bell is ringing
+    flash: bool,
     str_buf: String,
+    last_redraw: Duration,
 }
 
 impl WgpuContext {
@@ -37,31 +223,55 @@ impl WgpuContext {
         device: wgpu::Device,
         queue: wgpu::Queue,
         font_texture: FontTexture,
+        grid_size: (usize, usize),
         scale_factor: f32,
+        config: RenderConfig,
     ) -> Self {
+        let sample_count = config.sample_count;
         let cell_ctx = CellContext::new(
             &device,
             &queue,
             &viewport,
             font_texture,
-            FONT_SIZE,
+            grid_size,
+            config.font_size,
             scale_factor,
+            &config,
         );
+        let msaa_view = create_msaa_view(&device, &viewport, sample_count);
+        // compositor expects premultiplied alpha
+        let [r, g, b, a] = config.background.map(f64::from);
+        let clear_color = wgpu::Color {
+            r: r * a,
+            g: g * a,
+            b: b * a,
+            a,
+        };
 
         Self {
             cell_ctx,
             viewport,
             device,
             queue,
+            sample_count,
+            msaa_view,
+            clear_color,
+            flash: false,
             str_buf: String::new(),
+            last_redraw: Duration::ZERO,
         }
     }
 
+    pub fn set_flash(&mut self, flash: bool) {
+        self.flash = flash;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         log::trace!("Resize({}, {})", width, height);
 
         self.viewport.resize(&self.device, width, height);
-        self.cell_ctx.resize(width as _, height as _);
+        self.msaa_view = create_msaa_view(&self.device, &self.viewport, self.sample_count);
+        self.cell_ctx.resize(&self.queue, width as _, height as _);
         // TODO: update scroll_state
     }
 
@@ -69,7 +279,7 @@ impl WgpuContext {
     pub fn redraw(&mut self) {
         let start = Instant::now();
 
-        let frame = match self.viewport.get_current_texture() {
+        let frame = match self.viewport.get_current_texture(&self.device) {
             Some(frame) => frame,
             None => return,
         };
@@ -81,66 +291,179 @@ impl WgpuContext {
         let mut encoder = self
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-        {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("background"),
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: crate::DEFAULT_BG[0] as _,
-                            g: crate::DEFAULT_BG[1] as _,
-                            b: crate::DEFAULT_BG[2] as _,
-                            a: crate::DEFAULT_BG[3] as _,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-
-            self.cell_ctx.draw(&self.queue, &mut rpass);
-        }
+        self.draw(&mut encoder, &view);
 
         self.queue.submit(Some(encoder.finish()));
         frame.present();
 
-        let end = start.elapsed();
+        self.last_redraw = start.elapsed();
 
-        log::debug!("Redraw elapsed: {}us", end.as_micros());
+        log::debug!("Redraw elapsed: {}us", self.last_redraw.as_micros());
     }
+
+    /// Render current grid into `target` and read it back
+    ///
+    /// It works without window surface, `target` should fit current viewport
+    #[profiling::function]
+    pub fn render_to_image(&mut self, target: &OffscreenTarget) -> image::RgbaImage {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.draw(&mut encoder, target.view());
+        target.copy_to_buffer(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+
+        target.read(&self.device)
+    }
+
+    fn draw(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("background"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: self.msaa_view.as_ref().unwrap_or(view),
+                resolve_target: self.msaa_view.as_ref().map(|_| view),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(if self.flash {
+                        BELL_FLASH
+                    } else {
+                        self.clear_color
+                    }),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+
+        self.cell_ctx.draw(&self.queue, &mut rpass);
+    }
+}
+
+fn create_msaa_view(
+    device: &wgpu::Device,
+    viewport: &Viewport,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count == 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA texture"),
+        format: viewport.format(),
+        dimension: wgpu::TextureDimension::D2,
+        sample_count,
+        mip_level_count: 1,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        size: wgpu::Extent3d {
+            width: viewport.width(),
+            height: viewport.height(),
+            depth_or_array_layers: 1,
+        },
+    });
+
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+/// Load font from `path`, embedded font is used when it's `None` or loading failed
+///
+/// Fonts in `fallback_paths` are used for characters missing in main font
+pub fn load_font_faces(path: Option<&Path>, fallback_paths: &[PathBuf]) -> FontFaces {
+    let embedded = || swash::FontRef::from_index(FONT, 0).unwrap();
+
+    let font = match path {
+        Some(path) => font_texture::load_font(path).unwrap_or_else(|err| {
+            log::error!("Can't use font {}: {}", path.display(), err);
+            embedded()
+        }),
+        None => embedded(),
+    };
+
+    fallback_paths
+        .iter()
+        .fold(
+            FontFaces::new(font),
+            |faces, path| match font_texture::load_fallback_font(path) {
+                Ok(font) => faces.with_fallback(font),
+                Err(err) => {
+                    log::error!("Can't use fallback font {}: {}", path.display(), err);
+                    faces
+                }
+            },
+        )
 }
 
 #[profiling::function]
-pub fn generate_font_texture(scale_factor: f32) -> FontTexture {
-    FontTexture::new(
-        swash::FontRef::from_index(FONT, 0).unwrap(),
-        FONT_SIZE * scale_factor,
-    )
+pub fn generate_font_texture(
+    faces: FontFaces,
+    font_size: f32,
+    scale_factor: f32,
+    max_texture_width: u32,
+) -> FontTexture {
+    FontTexture::new(faces, font_size * scale_factor, max_texture_width)
 }
 
+/// Rasterize font again after font size or scale factor is changed
+///
+/// Grid is resized since cell size is changed
+fn rebuild_font(
+    ctx: &mut WgpuContext,
+    terminal: &mut Terminal,
+    master: &dyn MasterPty,
+    grid_size: &mut (usize, usize),
+    faces: &FontFaces,
+    font_size: f32,
+    scale_factor: f32,
+) {
+    let font_texture = generate_font_texture(
+        faces.clone(),
+        font_size,
+        scale_factor,
+        ctx.device.limits().max_texture_dimension_2d,
+    );
+    ctx.cell_ctx
+        .set_font_texture(&ctx.device, &ctx.queue, font_texture, scale_factor);
+    update_grid_size(ctx, terminal, master, grid_size);
+    ctx.cell_ctx.set_terminal(&ctx.device, &ctx.queue, terminal);
+}
+
+/// Window surface is `None` when it runs headless, then grid is only drawn by screenshot
 pub fn run(
-    surface: wgpu::Surface,
+    surface: Option<wgpu::Surface>,
     adapter: wgpu::Adapter,
     font_texture: FontTexture,
     width: u32,
     height: u32,
+    // configured grid is only initial size, it follows window after
+    mut grid_size: (usize, usize),
     scale_factor: f32,
+    mut config: RenderConfig,
+    theme: Option<Theme>,
     event_rx: Receiver<TemuEvent>,
+    window_tx: Sender<TemuEvent>,
     msg_rx: Receiver<Vec<Action>>,
     output: Box<dyn Write + Send>,
+    master: Box<dyn MasterPty + Send>,
 ) {
     profiling::register_thread!("Renderer");
 
+    // default background isn't drawn as cell, so window is cleared with theme background
+    if let Some([r, g, b]) = theme.as_ref().and_then(Theme::background_color) {
+        config.background = [r, g, b, config.background[3]];
+    }
+
     let mut terminal = Terminal::new(
         TerminalSize {
-            physical_cols: crate::COLUMN as _,
-            physical_rows: crate::ROW as _,
+            physical_cols: grid_size.0,
+            physical_rows: grid_size.1,
             pixel_height: 0,
             pixel_width: 0,
         },
-        Arc::new(crate::term::TerminalConfig),
+        Arc::new(crate::term::TerminalConfig::new(
+            config.background,
+            config.cursor_color,
+            config.scrollback_size,
+            theme.as_ref(),
+        )),
         "temu",
         "0.1.0",
         output,
@@ -152,7 +475,8 @@ pub fn run(
         &wgpu::DeviceDescriptor {
             label: None,
             features: wgpu::Features::empty(),
-            limits: wgpu::Limits::downlevel_defaults(),
+            // allow bigger atlas when adapter supports it
+            limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
         },
         None,
     ))
@@ -160,37 +484,222 @@ pub fn run(
 
     let mut current_size = (width, height);
 
-    let viewport = Viewport::new(current_size.0, current_size.1, &adapter, &device, surface);
-    let mut ctx = WgpuContext::new(viewport, device, queue, font_texture, scale_factor);
-    // let mut fps = fps_counter::FPSCounter::new();
-    // let mut fps_showtime = Instant::now();
+    let blink_interval = config.blink_interval;
+    let copy_on_select = config.copy_on_select;
+    let mut next_blink = Instant::now() + blink_interval;
+
+    let viewport = match surface {
+        Some(surface) => Viewport::new(
+            current_size.0,
+            current_size.1,
+            &adapter,
+            &device,
+            surface,
+            config.present_mode,
+        ),
+        None => Viewport::offscreen(current_size.0, current_size.1),
+    };
+    log::info!("Present mode: {:?}", viewport.present_mode());
+    // pipelines and MSAA texture are created with checked count
+    config.sample_count = config.supported_sample_count(&adapter, viewport.format());
+    log::info!("MSAA: {}x", config.sample_count);
+    // rasterize same faces again when scale factor is changed
+    let mut faces = font_texture.faces.clone();
+    let mut font_cache = FontCache::default();
+    let default_font_size = config.font_size;
+    let mut font_size = default_font_size;
+    let mut scale_factor = scale_factor;
+    let mut ctx = WgpuContext::new(
+        viewport,
+        device,
+        queue,
+        font_texture,
+        grid_size,
+        scale_factor,
+        config,
+    );
+    let mut fps = fps_counter::FPSCounter::new();
+    let mut show_stats = false;
+    // screenshots reuse it until window is resized
+    let mut offscreen: Option<OffscreenTarget> = None;
     let always_redraw = false;
     let mut cursor_pos = (0.0, 0.0);
     let mut pressed = false;
     let mut dragged = false;
+    let mut mods = Modifiers::empty();
+    // keep feeding terminal but freeze view while scroll lock is on
+    let mut paused = false;
+    let mut clipboard = match arboard::Clipboard::new() {
+        Ok(clipboard) => Some(clipboard),
+        Err(err) => {
+            log::error!("Failed to open clipboard: {}", err);
+            None
+        }
+    };
+
+    // visual bell is cleared after this
+    let mut bell_end: Option<Instant> = None;
+    // replies to application which don't go through terminal
+    let mut pty_writer = master
+        .try_clone_writer()
+        .expect("Failed to clone pty writer");
+    let frame_interval = Duration::from_secs(1) / config.max_fps.max(1);
+    // pty output waiting for instances to be rebuilt
+    let mut term_dirty = false;
+    let mut last_term_update = Instant::now();
+    // mirrors DECSET 2004 of terminal, multi-line paste is safe while it's set
+    let mut bracketed_paste = false;
+    // multi-line paste waiting for confirmation
+    let mut pending_paste: Option<String> = None;
 
     loop {
         profiling::scope!("Render loop");
 
+        let mut next_wakeup = bell_end.map_or(next_blink, |end| end.min(next_blink));
+        if term_dirty {
+            next_wakeup = next_wakeup.min(last_term_update + frame_interval);
+        }
         crossbeam_channel::select! {
             recv(msg_rx) -> actions => {
                 profiling::scope!("Process actions");
-                terminal.perform_actions(actions.unwrap());
-                ctx.cell_ctx.scroll_to_bottom(&terminal);
-                ctx.cell_ctx
-                    .set_terminal(&ctx.device, &ctx.queue, &terminal);
-                need_redraw = true;
+                let mut actions = match actions {
+                    Ok(actions) => actions,
+                    Err(_) => {
+                        log::info!("Shell closed pty, closing window");
+                        break;
+                    }
+                };
+                let drain_start = Instant::now();
+                loop {
+                    // actions before inline image are performed first to place it at cursor
+                    let mut performed = 0;
+                    for (index, action) in actions.iter().enumerate() {
+                        if let Action::OperatingSystemCommand(osc) = action {
+                            match **osc {
+                                OperatingSystemCommand::SetWindowTitle(ref title)
+                                | OperatingSystemCommand::SetIconNameAndWindowTitle(ref title) => {
+                                    window_tx.send(TemuEvent::SetTitle(title.clone())).ok();
+                                }
+                                // payload is already decoded by parser
+                                OperatingSystemCommand::SetSelection(_, ref text)
+                                    if config.allow_osc52 != Osc52::Disabled =>
+                                {
+                                    if let Some(clipboard) = clipboard.as_mut() {
+                                        if let Err(err) = clipboard.set_text(text.clone()) {
+                                            log::error!("Failed to set clipboard: {}", err);
+                                        }
+                                    }
+                                }
+                                OperatingSystemCommand::QuerySelection(selection)
+                                    if config.allow_osc52 == Osc52::ReadWrite =>
+                                {
+                                    if let Some(text) =
+                                        clipboard.as_mut().and_then(|c| c.get_text().ok())
+                                    {
+                                        // reply is encoded as OSC 52 set sequence
+                                        let reply =
+                                            OperatingSystemCommand::SetSelection(selection, text);
+                                        if let Err(err) = write!(pty_writer, "{}", reply) {
+                                            log::error!("Failed to reply clipboard: {}", err);
+                                        }
+                                    }
+                                }
+                                OperatingSystemCommand::ITermProprietary(ITermProprietary::File(
+                                    ref file,
+                                )) if file.inline => {
+                                    terminal.perform_actions(actions[performed..index].to_vec());
+                                    performed = index;
+                                    ctx.cell_ctx
+                                        .add_image(&ctx.device, &ctx.queue, &terminal, file);
+                                }
+                                _ => {}
+                            }
+                        }
+                        match action {
+                            Action::CSI(CSI::Mode(Mode::SetDecPrivateMode(DecPrivateMode::Code(
+                                DecPrivateModeCode::BracketedPaste,
+                            )))) => bracketed_paste = true,
+                            Action::CSI(CSI::Mode(Mode::ResetDecPrivateMode(DecPrivateMode::Code(
+                                DecPrivateModeCode::BracketedPaste,
+                            ))))
+                            | Action::Esc(Esc::Code(EscCode::FullReset)) => bracketed_paste = false,
+                            _ => {}
+                        }
+                        if let Action::Control(ControlCode::Bell) = action {
+                            match config.bell {
+                                Bell::Disabled => {}
+                                Bell::Visual => {
+                                    ctx.set_flash(true);
+                                    bell_end = Some(Instant::now() + BELL_DURATION);
+                                    need_redraw = true;
+                                }
+                                Bell::Audible => {
+                                    window_tx.send(TemuEvent::Bell).ok();
+                                }
+                            }
+                        }
+                    }
+                    actions.drain(..performed);
+                    terminal.perform_actions(actions);
+
+                    // apply output which is already waiting, but don't starve window events
+                    if drain_start.elapsed() >= frame_interval {
+                        break;
+                    }
+                    match msg_rx.try_recv() {
+                        Ok(more) => actions = more,
+                        Err(_) => break,
+                    }
+                }
+                if !paused {
+                    // instances are rebuilt at most once per frame
+                    term_dirty = true;
+                }
             }
             recv(event_rx) -> event => {
                 match event.unwrap() {
                     TemuEvent::Char(c) => {
+                        if pending_paste.take().is_some() {
+                            ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, "");
+                        }
+                        ctx.cell_ctx.reset_blink();
+                        // typing jumps back to prompt like other terminals
+                        if !ctx.cell_ctx.is_scrolled_to_bottom(&terminal) {
+                            ctx.cell_ctx.scroll_to_bottom(&terminal);
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
                         terminal
                             .key_down(KeyCode::Char(c), Default::default())
                             .unwrap();
                     }
+                    TemuEvent::Key { key, mods } => {
+                        if pending_paste.take().is_some() {
+                            ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, "");
+                        }
+                        ctx.cell_ctx.reset_blink();
+                        if !ctx.cell_ctx.is_scrolled_to_bottom(&terminal) {
+                            ctx.cell_ctx.scroll_to_bottom(&terminal);
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                        if let Err(err) =
+                            terminal.key_down(convert_key(key), convert_modifiers(mods))
+                        {
+                            log::error!("Failed to send key: {}", err);
+                        }
+                    }
                     TemuEvent::Close => {
                         break;
                     }
+                    TemuEvent::ResizeGrid { cols, rows } => {
+                        // grid is updated by Resize from window
+                        let (width, height) =
+                            ctx.cell_ctx.grid_pixel_size(cols.max(1), rows.max(1));
+                        window_tx.send(TemuEvent::RequestSize { width, height }).ok();
+                    }
                     TemuEvent::Resize { width, height } => {
                         if width == 0 || height == 0 {
                             continue;
@@ -199,16 +708,100 @@ pub fn run(
                             ctx.resize(width, height);
                             // need_redraw = true;
                             current_size = (width, height);
+
+                            if update_grid_size(&mut ctx, &mut terminal, &*master, &mut grid_size) {
+                                need_redraw = true;
+                            }
+                        }
+                    }
+                    TemuEvent::ScaleFactor(new_scale_factor) => {
+                        log::info!("Scale factor changed: {}", new_scale_factor);
+                        scale_factor = new_scale_factor;
+                        rebuild_font(
+                            &mut ctx,
+                            &mut terminal,
+                            &*master,
+                            &mut grid_size,
+                            &faces,
+                            font_size,
+                            scale_factor,
+                        );
+                        need_redraw = true;
+                    }
+                    TemuEvent::FontSizeDelta(delta) => {
+                        let new_font_size = (font_size + delta).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+                        if new_font_size != font_size {
+                            log::info!("Font size changed: {}", new_font_size);
+                            font_size = new_font_size;
+                            rebuild_font(
+                                &mut ctx,
+                                &mut terminal,
+                                &*master,
+                                &mut grid_size,
+                                &faces,
+                                font_size,
+                                scale_factor,
+                            );
+                            need_redraw = true;
                         }
                     }
+                    TemuEvent::FontSizeReset => {
+                        if font_size != default_font_size {
+                            font_size = default_font_size;
+                            rebuild_font(
+                                &mut ctx,
+                                &mut terminal,
+                                &*master,
+                                &mut grid_size,
+                                &faces,
+                                font_size,
+                                scale_factor,
+                            );
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::ReloadFont(path) => match font_cache.load(&path) {
+                        Ok(font) => {
+                            log::info!("Font reloaded: {}", path.display());
+                            // later rebuild by font size or scale factor keeps new font
+                            faces = faces.with_regular(font);
+                            rebuild_font(
+                                &mut ctx,
+                                &mut terminal,
+                                &*master,
+                                &mut grid_size,
+                                &faces,
+                                font_size,
+                                scale_factor,
+                            );
+                            need_redraw = true;
+                        }
+                        Err(err) => log::error!("Can't reload font {}: {}", path.display(), err),
+                    },
                     TemuEvent::CursorMove { x, y } => {
                         if pressed {
-                            if ctx.cell_ctx.drag(x, y) {
+                            // mode is only used when dragging starts
+                            let mode = if mods.contains(Modifiers::ALT) {
+                                SelectionMode::Block
+                            } else {
+                                SelectionMode::Cell
+                            };
+                            if ctx.cell_ctx.drag(x, y, mode, &terminal) {
+                                ctx.cell_ctx
+                                    .set_terminal(&ctx.device, &ctx.queue, &terminal);
                                 need_redraw = true;
                             }
                             dragged = true;
                         } else {
-                            if ctx.cell_ctx.hover(x, y) {
+                            let had_link = ctx.cell_ctx.hovered_link().is_some();
+                            if ctx.cell_ctx.hover(x, y, &terminal) {
+                                let has_link = ctx.cell_ctx.hovered_link().is_some();
+                                if had_link != has_link {
+                                    window_tx.send(TemuEvent::HoverLink(has_link)).ok();
+                                }
+                                // link underline is rebuilt with instances
+                                ctx.cell_ctx
+                                    .set_terminal(&ctx.device, &ctx.queue, &terminal);
                                 need_redraw = true;
                             }
                         }
@@ -221,43 +814,443 @@ pub fn run(
                     TemuEvent::Left(false) => {
                         if dragged {
                             ctx.cell_ctx.drag_end();
+                            if copy_on_select {
+                                if let (Some(clipboard), Some(text)) =
+                                    (clipboard.as_mut(), ctx.cell_ctx.selected_text(&terminal))
+                                {
+                                    if let Err(err) = set_primary_selection(clipboard, text) {
+                                        log::error!("Failed to set primary selection: {}", err);
+                                    }
+                                }
+                            }
+                        } else if let Some(uri) = ctx
+                            .cell_ctx
+                            .hovered_link()
+                            .filter(|_| mods.contains(Modifiers::CTRL))
+                        {
+                            open_url(uri);
                         } else {
-                            ctx.cell_ctx.click(cursor_pos.0, cursor_pos.1);
+                            if ctx.cell_ctx.click(cursor_pos.0, cursor_pos.1, &terminal) {
+                                ctx.cell_ctx
+                                    .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            }
                         }
                         need_redraw = true;
                         dragged = false;
                         pressed = false;
                     }
+                    TemuEvent::Middle(true) => {
+                        if let Some(text) = clipboard
+                            .as_mut()
+                            .and_then(|c| get_primary_selection(c).ok())
+                        {
+                            paste(
+                                &mut ctx,
+                                &mut terminal,
+                                text,
+                                config.paste_newline,
+                                bracketed_paste,
+                                &mut pending_paste,
+                            );
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::Middle(false) => {}
+                    TemuEvent::Focused(focused) => {
+                        // reports CSI I / CSI O when application enabled focus tracking
+                        terminal.focus_changed(focused);
+                        ctx.cell_ctx.set_focused(focused);
+                        next_blink = Instant::now() + blink_interval;
+                        need_redraw = true;
+                    }
                     TemuEvent::Redraw => {
                         need_redraw = true;
                     }
+                    TemuEvent::Modifiers(new_mods) => {
+                        mods = new_mods;
+                    }
+                    TemuEvent::SetTitle(_)
+                    | TemuEvent::Bell
+                    | TemuEvent::HoverLink(_)
+                    | TemuEvent::Exit => {}
+                    TemuEvent::ScrollUp if mods.contains(Modifiers::SHIFT) => {
+                        if ctx.cell_ctx.scroll_columns(-PAN_COLUMNS, &terminal) {
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::ScrollDown if mods.contains(Modifiers::SHIFT) => {
+                        if ctx.cell_ctx.scroll_columns(PAN_COLUMNS, &terminal) {
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
                     TemuEvent::ScrollUp => {
                         ctx.cell_ctx.scroll(-1, &terminal);
                         ctx.cell_ctx
                             .set_terminal(&ctx.device, &ctx.queue, &terminal);
                         need_redraw = true;
                     }
+                    TemuEvent::ScrollPixels(pixels) => {
+                        if ctx.cell_ctx.scroll_pixels(pixels, &terminal) {
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::ScrollPageUp => {
+                        ctx.cell_ctx.scroll_page(-1, &terminal);
+                        ctx.cell_ctx
+                            .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        need_redraw = true;
+                    }
+                    TemuEvent::ScrollPageDown => {
+                        ctx.cell_ctx.scroll_page(1, &terminal);
+                        ctx.cell_ctx
+                            .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        need_redraw = true;
+                    }
+                    TemuEvent::ScrollToTop => {
+                        ctx.cell_ctx.scroll_to_top(&terminal);
+                        ctx.cell_ctx
+                            .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        need_redraw = true;
+                    }
+                    TemuEvent::ScrollToBottom => {
+                        ctx.cell_ctx.scroll_to_bottom(&terminal);
+                        ctx.cell_ctx
+                            .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        need_redraw = true;
+                    }
                     TemuEvent::ScrollDown => {
                         ctx.cell_ctx.scroll(1, &terminal);
                         ctx.cell_ctx
                             .set_terminal(&ctx.device, &ctx.queue, &terminal);
                         need_redraw = true;
                     }
+                    TemuEvent::Copy => {
+                        if let (Some(clipboard), Some(text)) =
+                            (clipboard.as_mut(), ctx.cell_ctx.selected_text(&terminal))
+                        {
+                            if let Err(err) = clipboard.set_text(text) {
+                                log::error!("Failed to copy: {}", err);
+                            }
+                        }
+                    }
+                    TemuEvent::Paste(text) => {
+                        paste(
+                            &mut ctx,
+                            &mut terminal,
+                            text,
+                            config.paste_newline,
+                            bracketed_paste,
+                            &mut pending_paste,
+                        );
+                        need_redraw = true;
+                    }
+                    TemuEvent::Search(pattern) => {
+                        let rows = ctx.cell_ctx.search(&terminal, &pattern);
+                        log::info!("Search {:?}: {} rows matched", pattern, rows.len());
+                        // jump to the latest match
+                        ctx.cell_ctx.search_prev(&terminal);
+                        ctx.cell_ctx
+                            .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        need_redraw = true;
+                    }
+                    TemuEvent::SearchNext => {
+                        if ctx.cell_ctx.search_next(&terminal) {
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::SearchPrev => {
+                        if ctx.cell_ctx.search_prev(&terminal) {
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
+                    TemuEvent::Screenshot(path) => {
+                        // frame cap may hold latest output back
+                        if term_dirty {
+                            term_dirty = false;
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                        }
+                        let target = match offscreen.take() {
+                            Some(target) if target.fits(&ctx.viewport) => target,
+                            _ => OffscreenTarget::new(&ctx.device, &ctx.viewport),
+                        };
+                        let path = path.unwrap_or_else(default_screenshot_path);
+                        match ctx.render_to_image(&target).save(&path) {
+                            Ok(()) => log::info!("Screenshot saved to {}", path.display()),
+                            Err(err) => log::error!("Failed to save screenshot: {}", err),
+                        }
+                        offscreen = Some(target);
+                    }
+                    TemuEvent::ToggleStats => {
+                        show_stats = !show_stats;
+                        if !show_stats {
+                            ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, "");
+                        }
+                        need_redraw = true;
+                    }
+                    TemuEvent::ScrollLock => {
+                        paused = !paused;
+                        log::info!("Scroll lock: {}", paused);
+                        if !paused {
+                            ctx.cell_ctx.scroll_to_bottom(&terminal);
+                            ctx.cell_ctx
+                                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+                            need_redraw = true;
+                        }
+                    }
                 }
             }
+            default(next_wakeup.saturating_duration_since(Instant::now())) => {}
         };
 
+        let now = Instant::now();
+        if term_dirty && now >= last_term_update + frame_interval {
+            term_dirty = false;
+            last_term_update = now;
+            ctx.cell_ctx.reset_blink();
+            ctx.cell_ctx.scroll_to_bottom(&terminal);
+            ctx.cell_ctx
+                .set_terminal(&ctx.device, &ctx.queue, &terminal);
+            need_redraw = true;
+        }
+        if bell_end.map_or(false, |end| now >= end) {
+            bell_end = None;
+            ctx.set_flash(false);
+            need_redraw = true;
+        }
+        if now >= next_blink {
+            if ctx.cell_ctx.toggle_blink() {
+                need_redraw = true;
+            }
+            next_blink = now + blink_interval;
+        }
+
         if always_redraw || need_redraw {
+            let cur_fps = fps.tick();
+            if show_stats {
+                let stats = format!("{} FPS {}us", cur_fps, ctx.last_redraw.as_micros());
+                ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, &stats);
+            }
             ctx.redraw();
-            // let cur_fps = fps.tick();
-            // let now = Instant::now();
-            // if now > fps_showtime {
-            //     fps_showtime = now + Duration::from_secs(1);
-            //     println!("{}FPS", cur_fps);
-            // }
             need_redraw = always_redraw;
         }
 
         profiling::finish_frame!();
     }
 }
+
+/// `temu-screenshot-<unix time>.png` in current directory so screenshots don't overwrite others
+fn default_screenshot_path() -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    PathBuf::from(format!("temu-screenshot-{}.png", secs))
+}
+
+/// Send paste to terminal after applying newline policy
+fn paste(
+    ctx: &mut WgpuContext,
+    terminal: &mut Terminal,
+    text: String,
+    policy: PasteNewline,
+    bracketed_paste: bool,
+    pending_paste: &mut Option<String>,
+) {
+    let was_pending = pending_paste.take();
+    let body = text.trim_end_matches(['\r', '\n']);
+    let line_count = body.split(['\r', '\n']).filter(|l| !l.is_empty()).count();
+
+    if bracketed_paste || line_count <= 1 {
+        send_paste(terminal, &text);
+    } else {
+        match policy {
+            PasteNewline::Allow => send_paste(terminal, &text),
+            PasteNewline::Join => {
+                let joined = body
+                    .split(['\r', '\n'])
+                    .filter(|l| !l.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                send_paste(terminal, &joined);
+            }
+            PasteNewline::Confirm if was_pending.as_deref() == Some(&text) => {
+                send_paste(terminal, &text)
+            }
+            PasteNewline::Confirm => {
+                let message = format!("Paste {} lines? Paste again to confirm", line_count);
+                ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, &message);
+                *pending_paste = Some(text);
+                return;
+            }
+        }
+    }
+
+    if was_pending.is_some() {
+        ctx.cell_ctx.set_overlay(&ctx.device, &ctx.queue, "");
+    }
+}
+
+fn send_paste(terminal: &mut Terminal, text: &str) {
+    // prevent pasted text from escaping bracketed paste
+    let text = text.replace("\x1b[201~", "");
+    if let Err(err) = terminal.send_paste(&text) {
+        log::error!("Failed to paste: {}", err);
+    }
+}
+
+// only X11 and Wayland have primary selection
+cfg_if::cfg_if! {
+    if #[cfg(all(
+        unix,
+        not(any(target_os = "macos", target_os = "android", target_os = "emscripten"))
+    ))] {
+        fn set_primary_selection(
+            clipboard: &mut arboard::Clipboard,
+            text: String,
+        ) -> Result<(), arboard::Error> {
+            use arboard::{ClipboardExtLinux, LinuxClipboardKind};
+            clipboard.set_text_with_clipboard(text, LinuxClipboardKind::Primary)
+        }
+
+        fn get_primary_selection(
+            clipboard: &mut arboard::Clipboard,
+        ) -> Result<String, arboard::Error> {
+            use arboard::{ClipboardExtLinux, LinuxClipboardKind};
+            clipboard.get_text_with_clipboard(LinuxClipboardKind::Primary)
+        }
+    } else {
+        /// Selecting text shouldn't overwrite clipboard, so nothing is copied
+        fn set_primary_selection(
+            _clipboard: &mut arboard::Clipboard,
+            _text: String,
+        ) -> Result<(), arboard::Error> {
+            Ok(())
+        }
+
+        /// Middle click pastes clipboard instead
+        fn get_primary_selection(
+            clipboard: &mut arboard::Clipboard,
+        ) -> Result<String, arboard::Error> {
+            clipboard.get_text()
+        }
+    }
+}
+
+/// Schemes which can be opened by clicking link, others like `file:` may launch arbitrary program
+const OPEN_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Open url with default program of OS
+///
+/// Url comes from application in terminal, so it's never passed through shell
+fn open_url(url: &str) {
+    let allowed = url.split_once(':').map_or(false, |(scheme, _)| {
+        OPEN_URL_SCHEMES
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+    });
+    if !allowed {
+        log::warn!("Refuse to open {}, unsupported scheme", url);
+        return;
+    }
+
+    log::info!("Open {}", url);
+
+    #[cfg(windows)]
+    let child = std::process::Command::new("rundll32")
+        .args(["url.dll,FileProtocolHandler", url])
+        .spawn();
+    #[cfg(target_os = "macos")]
+    let child = std::process::Command::new("open").arg(url).spawn();
+    #[cfg(not(any(windows, target_os = "macos")))]
+    let child = std::process::Command::new("xdg-open").arg(url).spawn();
+
+    match child {
+        // reap opener so it doesn't remain as zombie
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(err) => log::error!("Failed to open {}: {}", url, err),
+    }
+}
+
+/// Resize terminal and pty to fit window, returns `true` when grid size is changed
+fn update_grid_size(
+    ctx: &mut WgpuContext,
+    terminal: &mut Terminal,
+    master: &dyn MasterPty,
+    grid_size: &mut (usize, usize),
+) -> bool {
+    let new_grid_size = (ctx.cell_ctx.cols(), ctx.cell_ctx.rows());
+    if *grid_size == new_grid_size {
+        return false;
+    }
+
+    *grid_size = new_grid_size;
+    let (cols, rows) = new_grid_size;
+    let [cell_width, cell_height] = ctx.cell_ctx.cell_size();
+    let pixel_width = (cols as f32 * cell_width) as usize;
+    let pixel_height = (rows as f32 * cell_height) as usize;
+    log::debug!("Resize grid ({}, {})", cols, rows);
+    ctx.cell_ctx.reserve_grid(&ctx.device, cols, rows);
+
+    let at_bottom = ctx.cell_ctx.is_scrolled_to_bottom(terminal);
+    // terminal rewraps scrollback and keeps cursor position
+    terminal.resize(rows, cols, pixel_width, pixel_height);
+    if let Err(err) = master.resize(PtySize {
+        cols: cols as _,
+        rows: rows as _,
+        pixel_width: pixel_width as _,
+        pixel_height: pixel_height as _,
+    }) {
+        log::error!("Failed to resize pty: {}", err);
+    }
+    if at_bottom {
+        ctx.cell_ctx.scroll_to_bottom(terminal);
+    } else {
+        // keep viewport while scrolled up, just clamp to new scrollback
+        ctx.cell_ctx.scroll(0, terminal);
+    }
+    ctx.cell_ctx.set_terminal(&ctx.device, &ctx.queue, terminal);
+
+    true
+}
+
+fn convert_key(key: Key) -> KeyCode {
+    match key {
+        Key::Char(c) => KeyCode::Char(c),
+        Key::Enter => KeyCode::Enter,
+        Key::Tab => KeyCode::Tab,
+        Key::Backspace => KeyCode::Backspace,
+        Key::Escape => KeyCode::Escape,
+        Key::Up => KeyCode::UpArrow,
+        Key::Down => KeyCode::DownArrow,
+        Key::Left => KeyCode::LeftArrow,
+        Key::Right => KeyCode::RightArrow,
+        Key::Home => KeyCode::Home,
+        Key::End => KeyCode::End,
+        Key::PageUp => KeyCode::PageUp,
+        Key::PageDown => KeyCode::PageDown,
+        Key::Insert => KeyCode::Insert,
+        Key::Delete => KeyCode::Delete,
+        Key::Function(n) => KeyCode::Function(n),
+    }
+}
+
+fn convert_modifiers(mods: Modifiers) -> KeyModifiers {
+    let mut ret = KeyModifiers::NONE;
+    ret.set(KeyModifiers::SHIFT, mods.contains(Modifiers::SHIFT));
+    ret.set(KeyModifiers::CTRL, mods.contains(Modifiers::CTRL));
+    ret.set(KeyModifiers::ALT, mods.contains(Modifiers::ALT));
+    ret.set(KeyModifiers::SUPER, mods.contains(Modifiers::SUPER));
+    ret
+}
@@ -1,23 +1,52 @@
-use etagere::{BucketedAtlasAllocator, Size};
+use std::fmt;
 
-#[derive(Clone, Copy, Default)]
+use etagere::{AllocId, BucketedAtlasAllocator, Size};
+
+#[derive(Clone, Copy)]
 pub struct Allocation {
+    pub id: AllocId,
     pub x: u32,
     pub y: u32,
     pub layer: u32,
 }
 
+/// Requested size doesn't fit in free space of atlas
+#[derive(Clone, Copy, Debug)]
+pub struct AllocError {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} doesn't fit in texture atlas",
+            self.width, self.height
+        )
+    }
+}
+
+impl std::error::Error for AllocError {}
+
 pub struct ArrayAllocator {
     inner: Vec<BucketedAtlasAllocator>,
     size: Size,
+    max_layer_count: u32,
 }
 
 impl ArrayAllocator {
+    /// Layers are added lazily when existing layers are full
     pub fn new(width: u32, height: u32) -> Self {
+        Self::with_layer_count(width, height, 1)
+    }
+
+    pub fn with_layer_count(width: u32, height: u32, layer_count: u32) -> Self {
         let size = Size::new(width as _, height as _);
         Self {
-            inner: vec![BucketedAtlasAllocator::new(size); 2],
+            inner: vec![BucketedAtlasAllocator::new(size); layer_count.max(1) as usize],
             size,
+            max_layer_count: u32::MAX,
         }
     }
 
@@ -25,32 +54,52 @@ impl ArrayAllocator {
         self.inner.len() as u32
     }
 
-    pub fn alloc(&mut self, width: u32, height: u32) -> Allocation {
+    /// `alloc` fails instead of adding layer over this count
+    pub fn set_max_layer_count(&mut self, max_layer_count: u32) {
+        self.max_layer_count = max_layer_count.max(1);
+    }
+
+    pub fn dealloc(&mut self, alloc: &Allocation) {
+        self.inner[alloc.layer as usize].deallocate(alloc.id);
+    }
+
+    /// Free every allocation and drop to single layer
+    pub fn reset(&mut self) {
+        self.inner.truncate(1);
+        self.inner[0].clear();
+    }
+
+    pub fn alloc(&mut self, width: u32, height: u32) -> Result<Allocation, AllocError> {
         let alloc_size = Size::new(width as _, height as _);
 
         for (layer, allocator) in self.inner.iter_mut().enumerate() {
             if let Some(alloc) = allocator.allocate(alloc_size) {
                 let [x, y] = alloc.rectangle.min.to_u32().to_array();
-                return Allocation {
+                return Ok(Allocation {
+                    id: alloc.id,
                     x,
                     y,
                     layer: layer as u32,
-                };
+                });
             }
         }
 
         let layer = self.inner.len();
+        if layer as u32 >= self.max_layer_count {
+            return Err(AllocError { width, height });
+        }
 
         let mut new_allocator = BucketedAtlasAllocator::new(self.size);
         let alloc = new_allocator
             .allocate(alloc_size)
-            .expect("Texture is too small");
+            .ok_or(AllocError { width, height })?;
         let [x, y] = alloc.rectangle.min.to_u32().to_array();
         self.inner.push(new_allocator);
-        Allocation {
+        Ok(Allocation {
+            id: alloc.id,
             x,
             y,
             layer: layer as u32,
-        }
+        })
     }
 }
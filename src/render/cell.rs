@@ -1,36 +1,141 @@
-use std::{mem, num::NonZeroU32};
+use std::{mem, num::NonZeroU32, ops::Range, sync::Arc, time::Instant};
 
 use ahash::AHashMap;
 use bytemuck::{Pod, Zeroable};
+use regex::Regex;
+use serde::Deserialize;
 // use rayon::prelude::*;
-use swash::{shape::ShapeContext, FontRef};
-use termwiz::{color::ColorAttribute, surface::SequenceNo};
+use swash::{scale::image::Content, shape::ShapeContext, FontRef, Metrics};
+use termwiz::{
+    cell::{Blink, Cell, CellAttributes, Intensity, Underline},
+    color::ColorAttribute,
+    escape::osc::ITermFileData,
+    hyperlink::Hyperlink,
+    surface::{CursorShape as TermCursorShape, SequenceNo},
+};
 use wgpu::SamplerBindingType;
 use wgpu_container::{WgpuCell, WgpuVec};
 
-use super::{FontTexture, GlyphCacheInfo, TEXTURE_WIDTH};
+use super::{
+    atlas::ArrayAllocator,
+    font_texture::{FontFaces, GlyphKey, GlyphRasterizer},
+    inline_image::ImageContext,
+    selection::{Selection, SelectionMode},
+    FontTexture, GlyphCacheInfo, RenderConfig,
+};
 use crate::render::Viewport;
-use wezterm_term::{StableRowIndex, Terminal};
+use wezterm_term::{color::ColorPalette, StableRowIndex, Terminal};
 
+const SCROLLBAR_WIDTH: f32 = 15.0;
 const SCROLLBAR_FOCUSED: [f32; 4] = [0.2, 0.2, 0.2, 1.0];
 const SCROLLBAR_UNFOCUSED: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+const SEARCH_MATCH: [f32; 4] = [0.5, 0.4, 0.0, 1.0];
+const SEARCH_CURRENT: [f32; 4] = [0.9, 0.6, 0.0, 1.0];
+const OVERLAY_TEXT: [f32; 3] = [1.0, 1.0, 0.0];
+/// Trailing punctuation is excluded so `(see https://a.b/c).` doesn't take `).`
+const URL_PATTERN: &str = r#"https?://[^\s<>"'`]*[^\s<>"'`.,;:!?)\]}]"#;
+const NO_LIGATURE_FEATURES: &[(&str, u16)] = &[("liga", 0), ("clig", 0), ("calt", 0)];
+/// Instances are rewritten every frame, so they are written to idle buffer in turn
+const INSTANCE_BUFFERS: usize = 3;
+/// Glyphs are evicted instead of growing atlas over this
+const MAX_ATLAS_LAYERS: u32 = 8;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlyphCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
 
 pub struct CellContext {
     pipeline: wgpu::RenderPipeline,
     text_pipeline: wgpu::RenderPipeline,
+    rect_pipeline: wgpu::RenderPipeline,
     ui_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
     bind_group: wgpu::BindGroup,
+    texture: wgpu::Texture,
+    font_texture_sampler: wgpu::Sampler,
+    /// Width and height of each atlas layer
+    texture_width: u32,
+    layer_count: u32,
+    allocator: ArrayAllocator,
+    color_texture: wgpu::Texture,
+    color_layer_count: u32,
+    color_allocator: ArrayAllocator,
+    rasterizer: GlyphRasterizer,
     instances: WgpuVec<CellVertex>,
     text_instances: WgpuVec<TextVertex>,
+    rect_instances: WgpuVec<RectVertex>,
+    /// text drawn over terminal like stats
+    overlay_instances: WgpuVec<TextVertex>,
+    overlay_text: String,
+    /// frame when overlay instances were built, they may point evicted glyphs after it
+    overlay_frame: u64,
+    images: ImageContext,
     ui: WgpuCell<Ui>,
     window_size: WgpuCell<WindowSize>,
-    font: FontRef<'static>,
+    faces: FontFaces,
     font_size: f32,
-    font_descent: f32,
-    glyph_cache: AHashMap<u16, GlyphCacheInfo>,
+    /// distance from bottom of cell to baseline
+    baseline_offset: f32,
+    /// multiplier of font height
+    line_height: f32,
+    /// logical pixels on each side of glyph
+    cell_padding: f32,
+    /// logical pixels between window edge and grid
+    window_padding: f32,
+    /// physical pixels from left of cell to glyph
+    glyph_left: f32,
+    underline_offset: f32,
+    strikeout_offset: f32,
+    stroke_size: f32,
+    glyph_cache: AHashMap<GlyphKey, Option<GlyphCacheInfo>>,
+    glyph_cache_stats: GlyphCacheStats,
+    /// incremented whenever instances are rebuilt
+    frame: u64,
+    /// cached rows older than this may point evicted glyphs
+    last_eviction_frame: u64,
+    shaped_glyphs: Vec<ShapedGlyph>,
+    ligatures: bool,
+    /// bold text with ANSI color 0-7 uses bright color 8-15
+    bold_is_bright: bool,
     prev_term_seqno: SequenceNo,
+    /// instances of each visible row, reused when row is not changed
+    row_cache: AHashMap<StableRowIndex, RowInstances>,
+    prev_alt_screen: bool,
     scroll_offset: StableRowIndex,
+    /// pixels of precise scroll which are not enough for a line yet
+    scroll_remainder: f32,
+    /// first column drawn at left edge, lines wider than window can be panned
+    column_offset: usize,
     mouse_status: MouseStatus,
+    /// used when application doesn't request cursor shape
+    cursor_shape: CursorShape,
+    /// used when application doesn't request cursor blinking
+    cursor_blink: bool,
+    cursor_blinking: bool,
+    cursor_inverse: bool,
+    /// character under block cursor, drawn over cursor
+    cursor_text_instances: WgpuVec<TextVertex>,
+    scrollbar_policy: ScrollbarPolicy,
+    /// scrollbar is drawn and takes mouse events
+    scrollbar_visible: bool,
+    focused: bool,
+    /// current instances have blinking text
+    has_blink_text: bool,
+    selection: Option<Selection>,
+    selection_color: [f32; 4],
+    /// link under mouse, every cell of it is underlined
+    hovered_link: Option<HoveredLink>,
+    url_regex: Regex,
+    /// (row, columns, url) of plain-text urls in visible rows
+    detected_links: Vec<(StableRowIndex, Range<usize>, String)>,
+    /// scroll offset and alt screen state when links are detected
+    detected_links_view: Option<(StableRowIndex, bool)>,
+    /// (row, begin column, end column) of search matches
+    search_matches: Vec<(StableRowIndex, usize, usize)>,
+    search_index: Option<usize>,
     shape_ctx: ShapeContext,
 }
 
@@ -40,22 +145,26 @@ impl CellContext {
         queue: &wgpu::Queue,
         viewport: &Viewport,
         font_texture: FontTexture,
+        (cols, rows): (usize, usize),
         font_size: f32,
         scale_factor: f32,
+        config: &RenderConfig,
     ) -> Self {
         profiling::scope!("Create CellContext");
 
-        let font_size = font_size * scale_factor;
+        let sample_count = config.sample_count;
 
-        let font = font_texture.font;
+        let font_size = font_size * scale_factor;
 
-        let metrics = font.metrics(&[]).scale(font_size);
-        // monospace width
-        assert!(metrics.is_monospace);
-        let glyph_metrics = font.glyph_metrics(&[]).scale(font_size);
-        let font_width = glyph_metrics.advance_width(font.charmap().map('M'));
-        let font_height = metrics.ascent + metrics.descent;
-        let cell_size = [font_width, font_height];
+        let mut font_texture = fit_font_texture(device, font_texture);
+        font_texture
+            .allocator
+            .set_max_layer_count(max_atlas_layers(device));
+        let texture_width = font_texture.texture_width;
+        let faces = font_texture.faces;
+        let cell_padding = config.cell_padding * scale_factor;
+        let (metrics, cell_size, baseline_offset) =
+            font_metrics(faces.regular(), font_size, config.line_height, cell_padding);
 
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("size_bind_group_layout"),
@@ -96,6 +205,16 @@ impl CellContext {
                     ty: wgpu::BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -139,7 +258,48 @@ impl CellContext {
                 ..Default::default()
             },
             depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        });
+
+        let rect_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("rect_pipeline"),
+            multiview: None,
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "rect_vs",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<RectVertex>() as _,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x4,
+                        1 => Float32x2,
+                        2 => Float32x2,
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "cell_fs",
+                targets: &[wgpu::ColorTargetState {
+                    format: viewport.format(),
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                front_face: wgpu::FrontFace::Cw,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
         });
 
         let ui_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -166,7 +326,10 @@ impl CellContext {
                 ..Default::default()
             },
             depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
         });
 
         let text_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -185,6 +348,8 @@ impl CellContext {
                         2 => Float32x2,
                         3 => Float32x3,
                         4 => Sint32,
+                        5 => Uint32,
+                        6 => Uint32,
                     ],
                 }],
             },
@@ -203,7 +368,10 @@ impl CellContext {
                 ..Default::default()
             },
             depth_stencil: None,
-            multisample: wgpu::MultisampleState::default(),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
         });
 
         let window_size = WgpuCell::new(
@@ -212,52 +380,35 @@ impl CellContext {
             WindowSize {
                 size: [viewport.width() as f32, viewport.height() as f32],
                 cell_size,
-                column: crate::COLUMN,
-                pad: 0,
+                column: cols as u32,
+                texture_width: texture_width as f32,
+                text_contrast: config.text_contrast.max(0.0),
+                srgb: viewport.format().describe().srgb as u32,
+                column_offset: 0,
+                padding: config.window_padding * scale_factor,
+                pad: [0; 2],
             },
         );
         let ui = WgpuCell::new(
             device,
             wgpu::BufferUsages::UNIFORM,
             Ui {
-                cursor_color: [1.0; 4],
+                cursor_color: config.cursor_color,
                 cursor_pos: [0.0; 2],
-                scrollbar_width: 15.0 * scale_factor,
+                scrollbar_width: scrollbar_width(config.scrollbar_policy, scale_factor),
                 scrollbar_height: 2.0,
                 scrollbar_bg: [1.0; 4],
                 scrollbar_fg: SCROLLBAR_UNFOCUSED,
                 scrollbar_top: -1.0,
-                pad: [0.0; 3],
+                cursor_shape: config.cursor_shape as u32,
+                cursor_hollow: 0,
+                cursor_visible: 1,
+                text_visible: 1,
+                pad: [0; 3],
             },
         );
 
-        let texture_size = wgpu::Extent3d {
-            width: TEXTURE_WIDTH,
-            height: TEXTURE_WIDTH,
-            depth_or_array_layers: font_texture.layer_count,
-        };
-
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Font texture"),
-            format: wgpu::TextureFormat::R8Unorm,
-            dimension: wgpu::TextureDimension::D2,
-            sample_count: 1,
-            mip_level_count: 1,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            size: texture_size,
-        });
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-
-        queue.write_texture(
-            texture.as_image_copy(),
-            &font_texture.data,
-            wgpu::ImageDataLayout {
-                bytes_per_row: NonZeroU32::new(TEXTURE_WIDTH),
-                rows_per_image: NonZeroU32::new(TEXTURE_WIDTH),
-                offset: 0,
-            },
-            texture_size,
-        );
+        let texture = upload_font_texture(device, queue, &font_texture);
 
         let font_texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
@@ -269,59 +420,230 @@ impl CellContext {
             ..Default::default()
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("window size bind group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: window_size.buffer().as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: ui.buffer().as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 5,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 6,
-                    resource: wgpu::BindingResource::Sampler(&font_texture_sampler),
-                },
-            ],
-        });
+        let (color_allocator, color_texture) = create_color_atlas(device, texture_width);
+
+        let images = ImageContext::new(
+            device,
+            &bind_group_layout,
+            &shader,
+            viewport.format(),
+            sample_count,
+        );
+
+        let bind_group = create_bind_group(
+            device,
+            &bind_group_layout,
+            &window_size,
+            &ui,
+            &texture,
+            &color_texture,
+            &font_texture_sampler,
+        );
+
+        // every cell has background instance and most of them have glyph
+        let grid_cells = cols * rows;
+        let mut text_instances = WgpuVec::with_ring(
+            device,
+            wgpu::BufferUsages::VERTEX,
+            grid_cells,
+            INSTANCE_BUFFERS,
+        );
+        // huge burst of text shouldn't keep gpu buffer large forever
+        text_instances.set_auto_shrink(true);
 
         Self {
             scroll_offset: 0,
+            scroll_remainder: 0.0,
+            column_offset: 0,
             prev_term_seqno: 0,
-            text_instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
-            instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
+            row_cache: AHashMap::new(),
+            prev_alt_screen: false,
+            text_instances,
+            instances: WgpuVec::with_ring(
+                device,
+                wgpu::BufferUsages::VERTEX,
+                grid_cells,
+                INSTANCE_BUFFERS,
+            ),
+            rect_instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
+            overlay_instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
+            overlay_text: String::new(),
+            overlay_frame: 0,
+            images,
             bind_group,
             glyph_cache: font_texture.glyph_cache,
+            glyph_cache_stats: GlyphCacheStats::default(),
+            frame: 0,
+            last_eviction_frame: 0,
+            allocator: font_texture.allocator,
+            rasterizer: font_texture.rasterizer,
+            layer_count: font_texture.layer_count,
+            texture,
+            color_layer_count: color_allocator.layer_count(),
+            color_allocator,
+            color_texture,
+            font_texture_sampler,
+            texture_width,
+            bind_group_layout,
+            shaped_glyphs: Vec::new(),
+            ligatures: config.ligatures,
+            bold_is_bright: config.bold_is_bright,
             shape_ctx: ShapeContext::new(),
             window_size,
             ui,
-            font,
+            faces,
             font_size,
-            font_descent: metrics.descent,
+            baseline_offset,
+            line_height: config.line_height,
+            cell_padding: config.cell_padding,
+            window_padding: config.window_padding,
+            glyph_left: cell_padding,
+            underline_offset: metrics.underline_offset,
+            strikeout_offset: metrics.strikeout_offset,
+            stroke_size: metrics.stroke_size.max(1.0),
             pipeline,
             text_pipeline,
+            rect_pipeline,
             ui_pipeline,
             mouse_status: MouseStatus::default(),
+            cursor_shape: config.cursor_shape,
+            cursor_blink: config.cursor_blink,
+            cursor_blinking: config.cursor_blink,
+            cursor_inverse: config.cursor_inverse,
+            cursor_text_instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
+            scrollbar_policy: config.scrollbar_policy,
+            scrollbar_visible: config.scrollbar_policy != ScrollbarPolicy::Never,
+            focused: true,
+            has_blink_text: false,
+            selection: None,
+            search_matches: Vec::new(),
+            search_index: None,
+            selection_color: config.selection_color,
+            hovered_link: None,
+            url_regex: Regex::new(URL_PATTERN).unwrap(),
+            detected_links: Vec::new(),
+            detected_links_view: None,
+        }
+    }
+
+    /// Replace font texture after scale factor is changed
+    ///
+    /// Every cached glyph is dropped since rasterization size is changed
+    pub fn set_font_texture(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        font_texture: FontTexture,
+        scale_factor: f32,
+    ) {
+        let mut font_texture = fit_font_texture(device, font_texture);
+        font_texture
+            .allocator
+            .set_max_layer_count(max_atlas_layers(device));
+        let cell_padding = self.cell_padding * scale_factor;
+        let (metrics, cell_size, baseline_offset) = font_metrics(
+            font_texture.faces.regular(),
+            font_texture.font_size,
+            self.line_height,
+            cell_padding,
+        );
+
+        let texture_width = font_texture.texture_width;
+        self.window_size.update(|size| {
+            size.cell_size = cell_size;
+            size.padding = self.window_padding * scale_factor;
+            size.texture_width = texture_width as f32;
+        });
+        self.ui.update(|ui| {
+            ui.scrollbar_width = scrollbar_width(self.scrollbar_policy, scale_factor);
+        });
+        self.font_size = font_texture.font_size;
+        self.baseline_offset = baseline_offset;
+        self.glyph_left = cell_padding;
+        self.underline_offset = metrics.underline_offset;
+        self.strikeout_offset = metrics.strikeout_offset;
+        self.stroke_size = metrics.stroke_size.max(1.0);
+
+        self.texture = upload_font_texture(device, queue, &font_texture);
+        if texture_width == self.texture_width {
+            self.color_allocator.reset();
+            if self.color_layer_count != 1 {
+                self.color_texture =
+                    create_font_texture(device, wgpu::TextureFormat::Rgba8Unorm, texture_width, 1);
+            }
+        } else {
+            let (color_allocator, color_texture) = create_color_atlas(device, texture_width);
+            self.color_allocator = color_allocator;
+            self.color_texture = color_texture;
+        }
+        self.color_layer_count = self.color_allocator.layer_count();
+        self.texture_width = texture_width;
+        self.faces = font_texture.faces;
+        self.glyph_cache = font_texture.glyph_cache;
+        self.allocator = font_texture.allocator;
+        self.rasterizer = font_texture.rasterizer;
+        self.layer_count = font_texture.layer_count;
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.window_size,
+            &self.ui,
+            &self.texture,
+            &self.color_texture,
+            &self.font_texture_sampler,
+        );
+        self.row_cache.clear();
+    }
+
+    fn mouse_target(&self, x: f32, y: f32) -> MouseTarget {
+        if self.scrollbar_visible {
+            self.ui.target(self.window_size.size, x, y)
+        } else {
+            MouseTarget::Empty
         }
     }
 
     #[profiling::function]
-    pub fn click(&mut self, _x: f32, _y: f32) -> bool {
-        false
+    pub fn click(&mut self, x: f32, y: f32, term: &Terminal) -> bool {
+        let [width, height] = self.window_size.size;
+
+        // clicking track outside of thumb pages up or down
+        if self.scrollbar_visible
+            && x >= width - self.ui.scrollbar_width
+            && self.mouse_target(x, y) == MouseTarget::Empty
+        {
+            let page = term.screen().physical_rows as StableRowIndex;
+            let y_ndc = 1.0 - (y * 2.0 / height);
+            if y_ndc > self.ui.scrollbar_top {
+                self.scroll(-page, term);
+            } else {
+                self.scroll(page, term);
+            }
+            return true;
+        }
+
+        self.selection.take().is_some()
+    }
+
+    /// Get selected text, see [`Selection::text`]
+    pub fn selected_text(&self, term: &Terminal) -> Option<String> {
+        self.selection
+            .as_ref()
+            .map(|selection| selection.text(term))
     }
 
     #[profiling::function]
-    pub fn hover(&mut self, x: f32, y: f32) -> bool {
-        let target = self.ui.target(self.window_size.size, x, y);
+    pub fn hover(&mut self, x: f32, y: f32, term: &Terminal) -> bool {
+        let target = self.mouse_target(x, y);
 
-        match self.mouse_status {
+        let link = match target {
+            MouseTarget::Empty => self.link_at(x, y, term),
+            MouseTarget::ScrollBar => None,
+        };
+        let link_changed = link != self.hovered_link;
+        self.hovered_link = link;
+
+        let target_changed = match self.mouse_status {
             MouseStatus::Hover(ref mut old_target) => {
                 if *old_target == target {
                     false
@@ -345,6 +667,95 @@ impl CellContext {
                 }
             }
             MouseStatus::Drag { .. } => unreachable!(),
+        };
+
+        target_changed || link_changed
+    }
+
+    /// Uri of hovered link
+    pub fn hovered_link(&self) -> Option<&str> {
+        match self.hovered_link.as_ref()? {
+            HoveredLink::Hyperlink(link) => Some(link.uri()),
+            HoveredLink::Detected { url, .. } => Some(url),
+        }
+    }
+
+    /// OSC 8 link takes precedence over detected url
+    fn link_at(&self, x: f32, y: f32, term: &Terminal) -> Option<HoveredLink> {
+        let (col, row) = self.pixel_to_cell(x, y);
+        let screen = term.screen();
+        let hyperlink = screen
+            .stable_row_to_phys(row)
+            .and_then(|phys| screen.lines.get(phys))
+            .and_then(|line| line.cells().get(col)?.attrs().hyperlink().cloned());
+        if let Some(link) = hyperlink {
+            return Some(HoveredLink::Hyperlink(link));
+        }
+
+        self.detected_links
+            .iter()
+            .find(|(link_row, cols, _)| *link_row == row && cols.contains(&col))
+            .map(|(row, cols, url)| HoveredLink::Detected {
+                row: *row,
+                cols: cols.clone(),
+                url: url.clone(),
+            })
+    }
+
+    /// Scan visible rows for plain-text urls, does nothing when they are not changed
+    #[profiling::function]
+    pub fn detect_links(&mut self, term: &Terminal) {
+        let screen = term.screen();
+        let start = self.scroll_offset;
+        let end = self.scroll_offset + screen.physical_rows as StableRowIndex;
+        let range = screen.stable_range(&(start..end));
+        let lines = &screen.lines.as_slices().0[range];
+        let view = (start, term.is_alt_screen_active());
+
+        if self.detected_links_view == Some(view)
+            && !lines
+                .iter()
+                .any(|line| line.changed_since(self.prev_term_seqno))
+        {
+            return;
+        }
+
+        self.detected_links_view = Some(view);
+        self.detected_links.clear();
+
+        let mut text = String::new();
+        let mut text_cols = Vec::new();
+
+        for (line_no, line) in lines.iter().enumerate() {
+            text.clear();
+            text_cols.clear();
+            for (x, cell) in line.cells().iter().enumerate() {
+                text.push_str(cell.str());
+                text_cols.resize(text.len(), x);
+            }
+
+            // cheap check before running regex
+            if !text.contains("http") {
+                continue;
+            }
+
+            let row = start + line_no as StableRowIndex;
+            for m in self.url_regex.find_iter(&text) {
+                let cols = text_cols[m.start()]..text_cols[m.end() - 1] + 1;
+                self.detected_links
+                    .push((row, cols, m.as_str().to_string()));
+            }
+        }
+
+        // hovered url may be gone with old content
+        if let Some(HoveredLink::Detected { row, ref cols, .. }) = self.hovered_link {
+            if !self
+                .detected_links
+                .iter()
+                .any(|(link_row, link_cols, _)| *link_row == row && link_cols == cols)
+            {
+                self.hovered_link = None;
+            }
         }
     }
 
@@ -364,8 +775,8 @@ impl CellContext {
     }
 
     #[profiling::function]
-    pub fn drag(&mut self, x: f32, y: f32) -> bool {
-        let target = self.ui.target(self.window_size.size, x, y);
+    pub fn drag(&mut self, x: f32, y: f32, mode: SelectionMode, term: &Terminal) -> bool {
+        let target = self.mouse_target(x, y);
 
         match self.mouse_status {
             MouseStatus::Hover(_) => {
@@ -381,19 +792,42 @@ impl CellContext {
                         });
                     }
                 }
+                if target == MouseTarget::Empty {
+                    let (col, row) = self.pixel_to_cell(x, y);
+                    self.selection = Some(Selection::new((row, col), mode));
+                }
                 self.mouse_status = MouseStatus::Drag {
                     target,
                     current: (x, y),
                     start: (x, y),
+                    start_offset: self.scroll_offset,
                 };
                 true
             }
             MouseStatus::Drag {
-                ref mut current, ..
+                ref mut current,
+                target,
+                start,
+                start_offset,
             } => {
                 let new_current = (x, y);
                 if *current != new_current {
                     *current = new_current;
+                    match target {
+                        MouseTarget::Empty => {
+                            let (col, row) = self.pixel_to_cell(x, y);
+                            if let Some(selection) = self.selection.as_mut() {
+                                selection.active = (row, col);
+                            }
+                        }
+                        MouseTarget::ScrollBar => {
+                            // whole scrollback is mapped to window height
+                            let total_rows = term.screen().lines.len() as f32;
+                            let rows = (y - start.1) / self.window_size.size[1] * total_rows;
+                            self.scroll_offset = start_offset;
+                            self.scroll(rows.round() as StableRowIndex, term);
+                        }
+                    }
                     true
                 } else {
                     false
@@ -402,28 +836,307 @@ impl CellContext {
         }
     }
 
-    pub fn resize(&mut self, width: f32, height: f32) {
-        self.window_size.update(|size| {
+    pub fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.cursor_shape = shape;
+        self.ui.update(|ui| {
+            ui.cursor_shape = shape as u32;
+        });
+    }
+
+    /// Unfocused window draws hollow cursor and stops blinking
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+        self.ui.update(|ui| {
+            ui.cursor_hollow = !focused as u32;
+            ui.cursor_visible = 1;
+        });
+    }
+
+    /// Toggle blink phase of cursor and text, returns `true` when redraw is needed
+    pub fn toggle_blink(&mut self) -> bool {
+        // blinking text keeps blinking without focus
+        let text_redraw = if self.has_blink_text {
+            self.ui.update(|ui| {
+                ui.text_visible ^= 1;
+            });
+            true
+        } else if self.ui.text_visible == 0 {
+            self.ui.update(|ui| {
+                ui.text_visible = 1;
+            });
+            true
+        } else {
+            false
+        };
+
+        let cursor_redraw = if self.cursor_blinking && self.focused {
+            self.ui.update(|ui| {
+                ui.cursor_visible ^= 1;
+            });
+            true
+        } else if self.ui.cursor_visible == 0 {
+            self.reset_blink();
+            true
+        } else {
+            false
+        };
+
+        text_redraw || cursor_redraw
+    }
+
+    /// Color of cursor quad, usually from palette of terminal
+    pub fn set_cursor_color(&mut self, color: [f32; 4]) {
+        self.ui.update(|ui| {
+            ui.cursor_color = color;
+        });
+    }
+
+    /// Make cursor visible again, e.g. after input
+    pub fn reset_blink(&mut self) {
+        if self.ui.cursor_visible == 0 {
+            self.ui.update(|ui| {
+                ui.cursor_visible = 1;
+            });
+        }
+    }
+
+    pub fn cell_size(&self) -> [f32; 2] {
+        self.window_size.cell_size
+    }
+
+    /// Physical inner size of window which fits `cols` x `rows` grid, padding and scrollbar
+    pub fn grid_pixel_size(&self, cols: u32, rows: u32) -> (u32, u32) {
+        let [width, height] = grid_size(
+            self.window_size.cell_size,
+            self.window_size.padding,
+            self.ui.scrollbar_width,
+            cols,
+            rows,
+        );
+        (width as u32, height as u32)
+    }
+
+    /// Number of columns fit in current window except padding and scrollbar
+    pub fn cols(&self) -> usize {
+        let width =
+            self.window_size.size[0] - self.window_size.padding * 2.0 - self.ui.scrollbar_width;
+        ((width / self.window_size.cell_size[0]) as usize).max(1)
+    }
+
+    /// Number of rows fit in current window except padding
+    pub fn rows(&self) -> usize {
+        let height = self.window_size.size[1] - self.window_size.padding * 2.0;
+        ((height / self.window_size.cell_size[1]) as usize).max(1)
+    }
+
+    /// Convert window pixel position into column and stable row
+    pub fn pixel_to_cell(&self, x: f32, y: f32) -> (usize, StableRowIndex) {
+        let (col, row) = pixel_to_grid(
+            [x, y],
+            self.window_size.padding,
+            self.window_size.cell_size,
+            (self.cols(), self.rows()),
+        );
+
+        (
+            col + self.column_offset,
+            self.scroll_offset + row as StableRowIndex,
+        )
+    }
+
+    /// Place inline image at cursor, should be called before terminal performs the sequence
+    pub fn add_image(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        term: &Terminal,
+        file: &ITermFileData,
+    ) {
+        self.images.add(
+            device,
+            queue,
+            term,
+            file,
+            self.window_size.cell_size,
+            self.window_size.size,
+        );
+    }
+
+    /// Reserve instance buffers for grid of new size
+    pub fn reserve_grid(&mut self, device: &wgpu::Device, cols: usize, rows: usize) {
+        self.instances.reserve(device, cols * rows);
+        self.text_instances.reserve(device, cols * rows);
+    }
+
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: f32, height: f32) {
+        self.window_size.update_now(queue, |size| {
             size.size = [width, height];
         });
+        self.row_cache.clear();
     }
 
     #[profiling::function]
     pub fn scroll(&mut self, offset: StableRowIndex, term: &Terminal) {
         let screen = term.screen();
-        let min = 0;
+        // evicted rows are not reachable anymore
+        let min = screen.phys_to_stable_row_index(0);
         let max = screen.visible_row_to_stable_row(0);
         self.scroll_offset = (self.scroll_offset + offset).max(min).min(max);
     }
 
+    /// Accumulate precise scroll, returns `true` when scrolled at least one line
+    pub fn scroll_pixels(&mut self, pixels: f32, term: &Terminal) -> bool {
+        let cell_height = self.window_size.cell_size[1];
+        self.scroll_remainder += pixels;
+        let lines = (self.scroll_remainder / cell_height).trunc();
+        if lines == 0.0 {
+            return false;
+        }
+        self.scroll_remainder -= lines * cell_height;
+
+        let prev = self.scroll_offset;
+        // positive pixels scroll up
+        self.scroll(-lines as StableRowIndex, term);
+        if prev == self.scroll_offset {
+            // don't keep accumulating at the ends
+            self.scroll_remainder = 0.0;
+        }
+        prev != self.scroll_offset
+    }
+
+    /// Scroll by visible rows, negative direction scrolls up
+    pub fn scroll_page(&mut self, dir: StableRowIndex, term: &Terminal) {
+        let page = term.screen().physical_rows as StableRowIndex;
+        self.scroll(dir.signum() * page, term);
+    }
+
+    pub fn scroll_to_top(&mut self, term: &Terminal) {
+        self.scroll_offset = term.screen().phys_to_stable_row_index(0);
+    }
+
     pub fn scroll_to_bottom(&mut self, term: &Terminal) {
         self.scroll_offset = term.screen().visible_row_to_stable_row(0);
     }
 
+    /// Pan horizontally, returns `true` when offset is changed
+    pub fn scroll_columns(&mut self, delta: isize, term: &Terminal) -> bool {
+        let prev = self.column_offset;
+        let offset = (self.column_offset as isize + delta).max(0) as usize;
+        self.column_offset = offset.min(self.max_column_offset(term));
+        self.column_offset != prev
+    }
+
+    /// Widest visible line can be panned until its end reaches right edge
+    fn max_column_offset(&self, term: &Terminal) -> usize {
+        let screen = term.screen();
+        let start = self.scroll_offset;
+        let end = self.scroll_offset + screen.physical_rows as StableRowIndex;
+        let range = screen.stable_range(&(start..end));
+        let widest = screen.lines.as_slices().0[range]
+            .iter()
+            .map(|line| line.cells().len())
+            .max()
+            .unwrap_or(0);
+
+        widest.saturating_sub(self.cols())
+    }
+
+    pub fn is_scrolled_to_bottom(&self, term: &Terminal) -> bool {
+        self.scroll_offset >= term.screen().visible_row_to_stable_row(0)
+    }
+
+    /// Find case-insensitive matches of pattern in whole scrollback, returns matched rows
+    ///
+    /// Empty pattern clears search
+    pub fn search(&mut self, term: &Terminal, pattern: &str) -> Vec<StableRowIndex> {
+        self.search_matches.clear();
+        self.search_index = None;
+        self.row_cache.clear();
+
+        let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+        if pattern.is_empty() {
+            return Vec::new();
+        }
+
+        let screen = term.screen();
+        let mut text = Vec::new();
+        let mut text_cols = Vec::new();
+        let mut rows = Vec::new();
+
+        for (phys, line) in screen.lines.iter().enumerate() {
+            let row = screen.phys_to_stable_row_index(phys);
+            text.clear();
+            text_cols.clear();
+            for (x, cell) in line.cells().iter().enumerate() {
+                for c in cell.str().chars().flat_map(char::to_lowercase) {
+                    text.push(c);
+                    text_cols.push(x);
+                }
+            }
+
+            let mut pos = 0;
+            while pos + pattern.len() <= text.len() {
+                if text[pos..pos + pattern.len()] == pattern[..] {
+                    let end = text_cols[pos + pattern.len() - 1] + 1;
+                    self.search_matches.push((row, text_cols[pos], end));
+                    if rows.last() != Some(&row) {
+                        rows.push(row);
+                    }
+                    pos += pattern.len();
+                } else {
+                    pos += 1;
+                }
+            }
+        }
+
+        rows
+    }
+
+    /// Scroll to next search match, wraps around at the bottom
+    pub fn search_next(&mut self, term: &Terminal) -> bool {
+        let len = self.search_matches.len();
+        if len == 0 {
+            return false;
+        }
+        let index = match self.search_index {
+            Some(index) => (index + 1) % len,
+            None => 0,
+        };
+        self.focus_search_match(index, term);
+        true
+    }
+
+    /// Scroll to previous search match, wraps around at the top
+    pub fn search_prev(&mut self, term: &Terminal) -> bool {
+        let len = self.search_matches.len();
+        if len == 0 {
+            return false;
+        }
+        let index = match self.search_index {
+            Some(0) | None => len - 1,
+            Some(index) => index - 1,
+        };
+        self.focus_search_match(index, term);
+        true
+    }
+
+    fn focus_search_match(&mut self, index: usize, term: &Terminal) {
+        self.search_index = Some(index);
+        // highlight color of previous match changes
+        self.row_cache.clear();
+        let row = self.search_matches[index].0;
+        let rows = term.screen().physical_rows as StableRowIndex;
+        if row < self.scroll_offset || row >= self.scroll_offset + rows {
+            self.scroll_offset = row - rows / 2;
+            self.scroll(0, term);
+        }
+    }
+
     #[profiling::function]
     pub fn set_terminal(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, term: &Terminal) {
         let screen = term.screen();
         let palette = term.get_config().color_palette();
+        let bold_is_bright = self.bold_is_bright;
 
         // self.desired_size = [
         //     screen.physical_cols as f32 * self.window_size.cell_size[0] + self.ui.scrollbar_width,
@@ -431,98 +1144,691 @@ impl CellContext {
         // ];
         let cell_size = self.window_size.cell_size;
 
-        {
-            profiling::scope!("Make cell instances");
-            let lines = screen.lines.as_slices().0;
-            let cells = (0..screen.physical_cols)
-                .into_iter()
-                .zip(0..screen.physical_rows)
-                .filter_map(|(x, y)| {
-                    let cell = lines[y].cells().get(x)?;
-
-                    if cell.attrs().background() != ColorAttribute::Default {
-                        let cell_pos = [x as f32 * cell_size[0], y as f32 * cell_size[1]];
-                        let color = palette.resolve_bg(cell.attrs().background());
-                        let (r, g, b, _) = color.to_tuple_rgba();
-                        Some(CellVertex {
-                            color: [r, g, b, 1.0],
-                            cell_pos,
-                            pad: [0.0; 2],
-                        })
-                    } else {
-                        None
-                    }
-                });
-            self.instances.cpu_buffer_mut().clear();
-            self.instances.cpu_buffer_mut().extend(cells);
+        self.detect_links(term);
+
+        // visible lines may be narrower after scrolling or new output
+        self.column_offset = self.column_offset.min(self.max_column_offset(term));
+        if self.window_size.column_offset != self.column_offset as u32 {
+            let column_offset = self.column_offset as u32;
+            self.window_size.update(|size| {
+                size.column_offset = column_offset;
+            });
+            // overlay stays at right edge of window
+            if !self.overlay_text.is_empty() {
+                let text = mem::take(&mut self.overlay_text);
+                self.set_overlay(device, queue, &text);
+            }
         }
 
         {
-            profiling::scope!("Make text instances");
+            profiling::scope!("Make instances");
+
+            self.frame += 1;
+            self.instances.clear();
+            self.text_instances.clear();
+            self.rect_instances.clear();
 
-            self.text_instances.cpu_buffer_mut().clear();
+            // stable rows of primary and alternate screen overlap
+            if term.is_alt_screen_active() != self.prev_alt_screen {
+                self.prev_alt_screen = term.is_alt_screen_active();
+                self.row_cache.clear();
+            }
+            let mut row_cache = mem::take(&mut self.row_cache);
+            let shape_start = Instant::now();
+            let mut shaped_rows = 0;
 
             let start = self.scroll_offset;
             let end = self.scroll_offset + screen.physical_rows as StableRowIndex;
             let range = screen.stable_range(&(start..end));
 
-            self.ui.update(|ui| {
-                ui.cursor_pos = [
-                    term.cursor_pos().x as _,
-                    screen.phys_row(term.cursor_pos().y) as _,
-                ];
-                let full_height = screen.lines.as_slices().0.len() as f32;
+            self.cursor_blinking = match term.cursor_pos().shape {
+                TermCursorShape::Default => self.cursor_blink,
+                TermCursorShape::BlinkingBlock
+                | TermCursorShape::BlinkingBar
+                | TermCursorShape::BlinkingUnderline => true,
+                TermCursorShape::SteadyBlock
+                | TermCursorShape::SteadyBar
+                | TermCursorShape::SteadyUnderline => false,
+            };
+            let cursor_shape = match term.cursor_pos().shape {
+                TermCursorShape::Default => self.cursor_shape,
+                TermCursorShape::BlinkingBlock | TermCursorShape::SteadyBlock => CursorShape::Block,
+                TermCursorShape::BlinkingBar | TermCursorShape::SteadyBar => CursorShape::Bar,
+                TermCursorShape::BlinkingUnderline | TermCursorShape::SteadyUnderline => {
+                    CursorShape::Underline
+                }
+            };
 
-                ui.scrollbar_top = 1.0 - (range.start as f32 / full_height) * 2.0;
+            let (r, g, b, _) = term.palette().cursor_bg.to_tuple_rgba();
+            self.set_cursor_color([r, g, b, 1.0]);
+
+            // cursor scrolled out of view is placed above window
+            let cursor_line = self
+                .cursor_line_no(term)
+                .map_or(-1.0, |line_no| line_no as f32);
+            self.ui.update_now(queue, |ui| {
+                ui.cursor_shape = cursor_shape as u32;
+                ui.cursor_pos = [term.cursor_pos().x as _, cursor_line];
+                let full_height = screen.lines.len() as f32;
+
+                ui.scrollbar_top = 1.0 - (range.start as f32 / full_height) * 2.0;
                 ui.scrollbar_height = -(range.len() as f32 / full_height) * 2.0;
             });
 
+            self.scrollbar_visible = match self.scrollbar_policy {
+                ScrollbarPolicy::Always => true,
+                ScrollbarPolicy::Auto => screen.lines.len() > screen.physical_rows,
+                ScrollbarPolicy::Never => false,
+            };
+
+            let row_count = range.len();
             for (line_no, line) in screen.lines.as_slices().0[range].iter().enumerate() {
-                // if !line.changed_since(self.prev_term_seqno) {
-                //     continue;
-                // }
-                let mut x = 0.0;
-                let mut shaper = self
-                    .shape_ctx
-                    .builder(self.font)
-                    .size(self.font_size)
-                    .build();
-                let s = line.as_str();
-                shaper.add_str(&s);
-                let mut cells = line.cells();
-
-                shaper.shape_with(|cluster| {
-                    let (cluster_cells, new_cells) = cells.split_at(cluster.glyphs.len());
-                    cells = new_cells;
-                    // let s = &s[cluster.source.to_range()];
-                    for (glyph, cell) in cluster.glyphs.iter().zip(cluster_cells) {
-                        if let Some(info) = self.glyph_cache.get(&glyph.id) {
-                            let (r, g, b, _) = palette
-                                .resolve_fg(cell.attrs().foreground())
-                                .to_tuple_rgba();
-                            self.text_instances.cpu_buffer_mut().push(TextVertex {
-                                offset: [
-                                    x + glyph.x + info.glyph_position[0],
+                let row = start + line_no as StableRowIndex;
+
+                if !line.changed_since(self.prev_term_seqno) {
+                    // evicted glyph may be reallocated elsewhere in atlas
+                    if let Some(mut cached) = row_cache
+                        .remove(&row)
+                        .filter(|cached| cached.frame >= self.last_eviction_frame)
+                    {
+                        cached.move_to(line_no, cell_size[1]);
+                        cached.frame = self.frame;
+                        for key in cached.glyphs.iter() {
+                            if let Some(Some(info)) = self.glyph_cache.get_mut(key) {
+                                info.last_used = self.frame;
+                            }
+                        }
+                        self.instances.extend_from_slice(&cached.cells);
+                        self.text_instances.extend_from_slice(&cached.texts);
+                        self.rect_instances.extend_from_slice(&cached.rects);
+                        self.row_cache.insert(row, cached);
+                        continue;
+                    }
+                }
+
+                shaped_rows += 1;
+                let cells_begin = self.instances.len();
+                let texts_begin = self.text_instances.len();
+                let rects_begin = self.rect_instances.len();
+                let mut row_glyphs = Vec::new();
+
+                for (x, cell) in line.cells().iter().enumerate() {
+                    let (fg, bg) = resolve_cell_colors(&palette, cell.attrs(), bold_is_bright);
+
+                    if cell.attrs().underline() != Underline::None || cell.attrs().strikethrough() {
+                        self.push_decorations(cell.attrs(), x, line_no, fg);
+                    }
+
+                    // default background is already filled by clear color
+                    let bg = match bg {
+                        Some(bg) => bg,
+                        None => continue,
+                    };
+                    self.instances.push(CellVertex {
+                        color: bg,
+                        cell_pos: [x as f32, line_no as f32],
+                        pad: [0.0; 2],
+                    });
+                }
+
+                for (index, &(_, begin, end)) in self
+                    .search_matches
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, m)| m.0 == row)
+                {
+                    let color = if self.search_index == Some(index) {
+                        SEARCH_CURRENT
+                    } else {
+                        SEARCH_MATCH
+                    };
+                    for x in begin..end {
+                        self.instances.push(CellVertex {
+                            color,
+                            cell_pos: [x as f32, line_no as f32],
+                            pad: [0.0; 2],
+                        });
+                    }
+                }
+
+                let cells = columned_cells(line.cells());
+                let mut run_start = 0;
+                let mut run_str = String::new();
+
+                // shape each run of cells which share same font face
+                while run_start < cells.len() {
+                    let face = self.cell_face(cells[run_start].1);
+                    let run_end = cells[run_start..]
+                        .iter()
+                        .position(|(_, cell)| self.cell_face(cell) != face)
+                        .map_or(cells.len(), |len| run_start + len);
+                    let run = &cells[run_start..run_end];
+
+                    run_str.clear();
+                    run.iter()
+                        .for_each(|(_, cell)| run_str.push_str(cell.str()));
+
+                    let builder = self
+                        .shape_ctx
+                        .builder(self.faces.get(face))
+                        .size(self.font_size);
+                    let mut shaper = if self.ligatures {
+                        builder.build()
+                    } else {
+                        builder.features(NO_LIGATURE_FEATURES).build()
+                    };
+                    shaper.add_str(&run_str);
+                    let mut cell_index = 0;
+                    let mut cell_start = 0;
+                    let is_fallback = self.faces.is_fallback(face);
+                    let glyph_left = self.glyph_left;
+
+                    let shaped_glyphs = &mut self.shaped_glyphs;
+                    shaped_glyphs.clear();
+
+                    shaper.shape_with(|cluster| {
+                        // find the cell where this cluster begins, ligature can span multiple cells
+                        while cell_index + 1 < run.len()
+                            && cell_start + run[cell_index].1.str().len()
+                                <= cluster.source.start as usize
+                        {
+                            cell_start += run[cell_index].1.str().len();
+                            cell_index += 1;
+                        }
+                        let (column, cell) = match run.get(cell_index) {
+                            Some(&(column, cell)) => (column, cell),
+                            None => return,
+                        };
+                        let ([r, g, b, _], _) =
+                            resolve_cell_colors(&palette, cell.attrs(), bold_is_bright);
+                        let blink = cell.attrs().blink() != Blink::None;
+                        // snap cluster to its column so cursor stays aligned
+                        let mut x = column as f32 * cell_size[0] + glyph_left;
+
+                        for glyph in cluster.glyphs {
+                            shaped_glyphs.push(ShapedGlyph {
+                                id: glyph.id,
+                                position: [x + glyph.x, glyph.y],
+                                color: [r, g, b],
+                                blink,
+                            });
+                            // fallback font has its own advance, keep it on cell grid
+                            x += if is_fallback {
+                                (glyph.advance / cell_size[0]).round() * cell_size[0]
+                            } else {
+                                glyph.advance
+                            };
+                        }
+                    });
+
+                    let shaped_glyphs = mem::take(&mut self.shaped_glyphs);
+                    for glyph in shaped_glyphs.iter() {
+                        if let Some(info) = self.ensure_glyph(device, queue, face, glyph.id) {
+                            row_glyphs.push((face, glyph.id));
+                            self.text_instances.push(TextVertex {
+                                offset: snap_to_pixel([
+                                    glyph.position[0] + info.glyph_position[0],
                                     cell_size[1] * (line_no + 1) as f32
-                                        - (info.glyph_position[1] + glyph.y + self.font_descent),
-                                ],
+                                        - (info.glyph_position[1]
+                                            + glyph.position[1]
+                                            + self.baseline_offset),
+                                ]),
                                 tex_offset: info.tex_position,
                                 tex_size: info.tex_size,
-                                color: [r, g, b],
+                                color: glyph.color,
                                 layer: info.layer as i32,
+                                is_color: info.is_color as u32,
+                                blink: glyph.blink as u32,
                             });
                         }
-                        x += glyph.advance;
                     }
-                });
+                    self.shaped_glyphs = shaped_glyphs;
+
+                    run_start = run_end;
+                }
+
+                self.row_cache.insert(
+                    row,
+                    RowInstances {
+                        line_no,
+                        frame: self.frame,
+                        glyphs: row_glyphs,
+                        cells: self.instances.cpu_buffer()[cells_begin..].to_vec(),
+                        texts: self.text_instances.cpu_buffer()[texts_begin..].to_vec(),
+                        rects: self.rect_instances.cpu_buffer()[rects_begin..].to_vec(),
+                    },
+                );
+            }
+
+            log::debug!(
+                "Reshaped {}/{} rows in {:?}",
+                shaped_rows,
+                row_count,
+                shape_start.elapsed()
+            );
+            log::trace!("{:?}", self.glyph_cache_stats());
+            self.has_blink_text = self
+                .text_instances
+                .cpu_buffer()
+                .iter()
+                .any(|v| v.blink != 0);
+
+            // selection changes while dragging, so it's not cached with rows
+            if let Some(selection) = self.selection {
+                let columns = self.column_offset + self.cols();
+                let rows = selection.rows();
+                let first = (*rows.start()).max(start);
+                let last = (*rows.end()).min(start + row_count as StableRowIndex - 1);
+
+                for row in first..=last {
+                    let line_no = (row - start) as f32;
+                    for x in selection.columns(row, columns) {
+                        self.instances.push(CellVertex {
+                            color: self.selection_color,
+                            cell_pos: [x as f32, line_no],
+                            pad: [0.0; 2],
+                        });
+                    }
+                }
             }
+
+            // hovered link changes with mouse, so it's not cached with rows either
+            if let Some(link) = self.hovered_link.clone() {
+                let [cell_width, cell_height] = cell_size;
+                let range = screen.stable_range(&(start..end));
+                for (line_no, line) in screen.lines.as_slices().0[range].iter().enumerate() {
+                    let row = start + line_no as StableRowIndex;
+                    let top = cell_height * (line_no + 1) as f32
+                        - self.baseline_offset
+                        - self.underline_offset;
+                    for (x, cell) in line.cells().iter().enumerate() {
+                        let linked = match link {
+                            HoveredLink::Hyperlink(ref link) => {
+                                cell.attrs().hyperlink() == Some(link)
+                            }
+                            HoveredLink::Detected {
+                                row: link_row,
+                                ref cols,
+                                ..
+                            } => link_row == row && cols.contains(&x),
+                        };
+                        if !linked {
+                            continue;
+                        }
+                        let (fg, _) = resolve_cell_colors(&palette, cell.attrs(), bold_is_bright);
+                        self.rect_instances.push(RectVertex {
+                            color: fg,
+                            position: [x as f32 * cell_width, top],
+                            size: [cell_width, self.stroke_size],
+                        });
+                    }
+                }
+            }
+        }
+
+        // glyphs used in this frame are never evicted, so rebuild them after every eviction
+        self.update_cursor_text(device, queue, term);
+        if !self.overlay_text.is_empty() && self.last_eviction_frame > self.overlay_frame {
+            let text = mem::take(&mut self.overlay_text);
+            self.set_overlay(device, queue, &text);
         }
+        self.images
+            .update(device, queue, term, self.scroll_offset, cell_size);
 
-        self.instances.write(device, queue);
-        self.text_instances.write(device, queue);
+        self.instances.write_dirty(device, queue);
+        self.text_instances.write_dirty(device, queue);
+        self.rect_instances.write_dirty(device, queue);
         self.prev_term_seqno = term.current_seqno();
     }
 
+    /// Get cached glyph or rasterize it into atlas
+    pub fn ensure_glyph(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        face: usize,
+        id: u16,
+    ) -> Option<GlyphCacheInfo> {
+        if let Some(info) = self.glyph_cache.get_mut(&(face, id)) {
+            self.glyph_cache_stats.hits += 1;
+            if let Some(info) = info {
+                info.last_used = self.frame;
+            }
+            return *info;
+        }
+        self.glyph_cache_stats.misses += 1;
+
+        profiling::scope!("Rasterize glyph");
+
+        if !self
+            .rasterizer
+            .rasterize(self.faces.get(face), self.font_size, id)
+        {
+            self.glyph_cache.insert((face, id), None);
+            return None;
+        }
+
+        let image = self.rasterizer.image();
+        let placement = image.placement;
+        let is_color = image.content == Content::Color;
+        let alloc = loop {
+            let allocator = if is_color {
+                &mut self.color_allocator
+            } else {
+                &mut self.allocator
+            };
+            match allocator.alloc(placement.width, placement.height) {
+                Ok(alloc) => break alloc,
+                Err(err) => {
+                    if self.evict_glyphs(is_color) {
+                        continue;
+                    }
+                    log::warn!("Can't cache glyph {}: {}", id, err);
+                    // retry on next frame unless glyph is bigger than whole layer
+                    if placement.width.max(placement.height) > self.texture_width {
+                        self.glyph_cache.insert((face, id), None);
+                    }
+                    return None;
+                }
+            }
+        };
+        let layer_count = if is_color {
+            self.color_layer_count
+        } else {
+            self.layer_count
+        };
+
+        if alloc.layer >= layer_count {
+            self.grow_texture(device, queue, is_color);
+        }
+
+        let (texture, bytes_per_pixel) = if is_color {
+            (&self.color_texture, 4)
+        } else {
+            (&self.texture, 1)
+        };
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: alloc.x,
+                    y: alloc.y,
+                    z: alloc.layer,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &self.rasterizer.image().data,
+            wgpu::ImageDataLayout {
+                bytes_per_row: NonZeroU32::new(placement.width * bytes_per_pixel),
+                rows_per_image: NonZeroU32::new(placement.height),
+                offset: 0,
+            },
+            wgpu::Extent3d {
+                width: placement.width,
+                height: placement.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let mut info = GlyphCacheInfo::new(alloc, placement, is_color);
+        info.last_used = self.frame;
+        self.glyph_cache.insert((face, id), Some(info));
+        Some(info)
+    }
+
+    /// Draw single line `text` at top right corner over terminal, empty text removes overlay
+    pub fn set_overlay(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, text: &str) {
+        self.overlay_instances.clear();
+        self.overlay_text.clear();
+        self.overlay_text.push_str(text);
+        self.overlay_frame = self.frame;
+
+        let [cell_width, cell_height] = self.window_size.cell_size;
+        let face = self.faces.face_id(false, false);
+        let font = self.faces.get(face);
+        // cancel out panning and padding which are applied to every text in shader
+        let left = self.window_size.size[0] + self.column_offset as f32 * cell_width
+            - self.window_size.padding * 2.0
+            - self.ui.scrollbar_width
+            - text.chars().count() as f32 * cell_width;
+
+        for (x, c) in text.chars().enumerate() {
+            if let Some(info) = self.ensure_glyph(device, queue, face, font.charmap().map(c)) {
+                self.overlay_instances.push(TextVertex {
+                    offset: snap_to_pixel([
+                        left + x as f32 * cell_width + self.glyph_left + info.glyph_position[0],
+                        cell_height - (info.glyph_position[1] + self.baseline_offset),
+                    ]),
+                    tex_offset: info.tex_position,
+                    tex_size: info.tex_size,
+                    color: OVERLAY_TEXT,
+                    layer: info.layer,
+                    is_color: info.is_color as u32,
+                    blink: 0,
+                });
+            }
+        }
+
+        self.overlay_instances.write_dirty(device, queue);
+    }
+
+    /// Row of cursor from top of window, `None` when it's scrolled out
+    fn cursor_line_no(&self, term: &Terminal) -> Option<usize> {
+        let screen = term.screen();
+        let row = screen.visible_row_to_stable_row(term.cursor_pos().y) - self.scroll_offset;
+        (0..screen.physical_rows as StableRowIndex)
+            .contains(&row)
+            .then(|| row as usize)
+    }
+
+    /// Rebuild character under cursor in its background color
+    fn update_cursor_text(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, term: &Terminal) {
+        self.cursor_text_instances.clear();
+
+        if self.cursor_inverse {
+            let screen = term.screen();
+            let palette = term.get_config().color_palette();
+            let cursor = term.cursor_pos();
+            let phys = screen.phys_row(cursor.y);
+            let cell = screen
+                .lines
+                .get(phys)
+                .and_then(|line| line.cells().get(cursor.x));
+
+            // same row as cursor quad in ui_vs
+            if let (Some(cell), Some(line_no)) = (cell, self.cursor_line_no(term)) {
+                let [cell_width, cell_height] = self.window_size.cell_size;
+                let face = self.cell_face(cell);
+                let glyph_id = match cell.str().chars().next() {
+                    Some(c) if c != ' ' => self.faces.get(face).charmap().map(c),
+                    _ => 0,
+                };
+                let (_, bg) = resolve_cell_colors(&palette, cell.attrs(), self.bold_is_bright);
+                let bg = bg.unwrap_or_else(|| {
+                    let (r, g, b, a) = palette.background.to_tuple_rgba();
+                    [r, g, b, a]
+                });
+
+                if glyph_id != 0 {
+                    if let Some(info) = self.ensure_glyph(device, queue, face, glyph_id) {
+                        self.cursor_text_instances.push(TextVertex {
+                            offset: snap_to_pixel([
+                                cursor.x as f32 * cell_width
+                                    + self.glyph_left
+                                    + info.glyph_position[0],
+                                cell_height * (line_no + 1) as f32
+                                    - (info.glyph_position[1] + self.baseline_offset),
+                            ]),
+                            tex_offset: info.tex_position,
+                            tex_size: info.tex_size,
+                            color: [bg[0], bg[1], bg[2]],
+                            layer: info.layer,
+                            is_color: info.is_color as u32,
+                            blink: 0,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.cursor_text_instances.write_dirty(device, queue);
+    }
+
+    pub fn glyph_cache_stats(&self) -> GlyphCacheStats {
+        self.glyph_cache_stats
+    }
+
+    /// Evict least recently used quarter of glyphs which are not drawn in current frame
+    ///
+    /// Returns `false` when there's nothing to evict
+    fn evict_glyphs(&mut self, is_color: bool) -> bool {
+        let frame = self.frame;
+        let mut candidates = self
+            .glyph_cache
+            .iter()
+            .filter_map(|(key, info)| match info {
+                Some(info) if info.is_color == is_color && info.last_used < frame => {
+                    Some((info.last_used, *key))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return false;
+        }
+
+        candidates.sort_unstable_by_key(|&(last_used, _)| last_used);
+        let count = (candidates.len() / 4).max(1);
+        let allocator = if is_color {
+            &mut self.color_allocator
+        } else {
+            &mut self.allocator
+        };
+        for (_, key) in candidates[..count].iter() {
+            if let Some(Some(info)) = self.glyph_cache.remove(key) {
+                allocator.dealloc(&info.alloc);
+            }
+        }
+        log::debug!("Evict {} glyphs", count);
+        self.glyph_cache_stats.evictions += count as u64;
+        self.last_eviction_frame = frame;
+        true
+    }
+
+    /// Recreate font texture with new layers and copy old layers into it
+    fn grow_texture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, is_color: bool) {
+        let (texture, old_layer_count, layer_count, format) = if is_color {
+            (
+                &mut self.color_texture,
+                &mut self.color_layer_count,
+                self.color_allocator.layer_count(),
+                wgpu::TextureFormat::Rgba8Unorm,
+            )
+        } else {
+            (
+                &mut self.texture,
+                &mut self.layer_count,
+                self.allocator.layer_count(),
+                wgpu::TextureFormat::R8Unorm,
+            )
+        };
+        log::debug!(
+            "Grow {:?} font texture {} -> {}",
+            format,
+            old_layer_count,
+            layer_count
+        );
+
+        let new_texture = create_font_texture(device, format, self.texture_width, layer_count);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_texture(
+            texture.as_image_copy(),
+            new_texture.as_image_copy(),
+            wgpu::Extent3d {
+                width: self.texture_width,
+                height: self.texture_width,
+                depth_or_array_layers: *old_layer_count,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        *texture = new_texture;
+        *old_layer_count = layer_count;
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.window_size,
+            &self.ui,
+            &self.texture,
+            &self.color_texture,
+            &self.font_texture_sampler,
+        );
+    }
+
+    /// Push underline and strikethrough rects for cell
+    fn push_decorations(
+        &mut self,
+        attrs: &CellAttributes,
+        x: usize,
+        line_no: usize,
+        color: [f32; 4],
+    ) {
+        let [cell_width, cell_height] = self.window_size.cell_size;
+        let left = x as f32 * cell_width;
+        let baseline = cell_height * (line_no + 1) as f32 - self.baseline_offset;
+        let underline_top = baseline - self.underline_offset;
+        let strikeout_top = baseline - self.strikeout_offset;
+        let stroke = self.stroke_size;
+        let rects = &mut self.rect_instances;
+        let mut push = |x: f32, y: f32, width: f32| {
+            rects.push(RectVertex {
+                color,
+                position: [x, y],
+                size: [width, stroke],
+            });
+        };
+
+        match attrs.underline() {
+            Underline::None => {}
+            Underline::Double => {
+                push(left, underline_top, cell_width);
+                push(left, underline_top + stroke * 2.0, cell_width);
+            }
+            Underline::Curly => {
+                // approximate wave with 4 segments per cell
+                let segment = cell_width / 4.0;
+                for (i, dy) in [0.0, stroke, 0.0, -stroke].iter().enumerate() {
+                    push(left + segment * i as f32, underline_top + dy, segment);
+                }
+            }
+            _ => {
+                push(left, underline_top, cell_width);
+            }
+        }
+
+        if attrs.strikethrough() {
+            push(left, strikeout_top, cell_width);
+        }
+    }
+
+    fn face_id(&self, attrs: &CellAttributes) -> usize {
+        self.faces
+            .face_id(attrs.intensity() == Intensity::Bold, attrs.italic())
+    }
+
+    /// Face of cell style, or fallback face when style face lacks the character
+    fn cell_face(&self, cell: &Cell) -> usize {
+        let face = self.face_id(cell.attrs());
+        match cell.str().chars().next() {
+            Some(c) if !c.is_ascii() => self.faces.face_for_char(face, c),
+            _ => face,
+        }
+    }
+
     #[profiling::function]
     pub fn draw<'a>(&'a mut self, queue: &wgpu::Queue, rpass: &mut wgpu::RenderPass<'a>) {
         self.window_size.flush(queue);
@@ -530,7 +1836,7 @@ impl CellContext {
 
         rpass.set_bind_group(0, &self.bind_group, &[]);
 
-        if self.instances.len() != 0 {
+        if !self.instances.is_empty() {
             rpass.push_debug_group("Draw cell");
             rpass.set_pipeline(&self.pipeline);
             rpass.set_vertex_buffer(0, self.instances.gpu_buffer().slice(..));
@@ -538,18 +1844,408 @@ impl CellContext {
             rpass.pop_debug_group();
         }
 
+        self.images.draw(rpass);
+
         rpass.push_debug_group("Draw text");
         rpass.set_pipeline(&self.text_pipeline);
         rpass.set_vertex_buffer(0, self.text_instances.gpu_buffer().slice(..));
         rpass.draw(0..4, 0..self.text_instances.len() as _);
         rpass.pop_debug_group();
 
+        if !self.rect_instances.is_empty() {
+            rpass.push_debug_group("Draw decoration");
+            rpass.set_pipeline(&self.rect_pipeline);
+            rpass.set_vertex_buffer(0, self.rect_instances.gpu_buffer().slice(..));
+            rpass.draw(0..4, 0..self.rect_instances.len() as _);
+            rpass.pop_debug_group();
+        }
+
         rpass.push_debug_group("Draw ui");
         rpass.set_pipeline(&self.ui_pipeline);
-        // cursor, scrollbar outer, scrollbar inner
-        rpass.draw(0..4, 0..3);
+        // cursor, scrollbar outer, scrollbar inner, hollow cursor edges
+        if self.scrollbar_visible {
+            rpass.draw(0..4, 0..7);
+        } else {
+            rpass.draw(0..4, 0..1);
+            rpass.draw(0..4, 3..7);
+        }
         rpass.pop_debug_group();
+
+        // hollow cursor and bar or underline don't cover character
+        let covered = self.ui.cursor_visible != 0
+            && self.ui.cursor_hollow == 0
+            && self.ui.cursor_shape == CursorShape::Block as u32;
+        if covered && !self.cursor_text_instances.is_empty() {
+            rpass.push_debug_group("Draw cursor text");
+            rpass.set_pipeline(&self.text_pipeline);
+            rpass.set_vertex_buffer(0, self.cursor_text_instances.gpu_buffer().slice(..));
+            rpass.draw(0..4, 0..self.cursor_text_instances.len() as _);
+            rpass.pop_debug_group();
+        }
+
+        if !self.overlay_instances.is_empty() {
+            rpass.push_debug_group("Draw overlay");
+            rpass.set_pipeline(&self.text_pipeline);
+            rpass.set_vertex_buffer(0, self.overlay_instances.gpu_buffer().slice(..));
+            rpass.draw(0..4, 0..self.overlay_instances.len() as _);
+            rpass.pop_debug_group();
+        }
+    }
+}
+
+/// Round glyph position to whole pixels, `Nearest` sampler distorts glyph placed between pixels
+fn snap_to_pixel([x, y]: [f32; 2]) -> [f32; 2] {
+    [x.round(), y.round()]
+}
+
+/// Column and row of grid under window pixel, clamped to `cols` x `rows` grid
+///
+/// `padding` around grid is subtracted first so its pixels map to nearest edge cell
+fn pixel_to_grid(
+    [x, y]: [f32; 2],
+    padding: f32,
+    [cell_width, cell_height]: [f32; 2],
+    (cols, rows): (usize, usize),
+) -> (usize, usize) {
+    let col = (((x - padding).max(0.0) / cell_width) as usize).min(cols - 1);
+    let row = (((y - padding).max(0.0) / cell_height) as usize).min(rows - 1);
+
+    (col, row)
+}
+
+/// (column, cell) of line without trailing spacers of wide cells
+fn columned_cells(cells: &[Cell]) -> Vec<(usize, &Cell)> {
+    let mut spacers = 0;
+    cells
+        .iter()
+        .enumerate()
+        .filter(|(_, cell)| {
+            if spacers > 0 {
+                spacers -= 1;
+                false
+            } else {
+                spacers = cell.width().saturating_sub(1);
+                true
+            }
+        })
+        .collect()
+}
+
+/// Resolve foreground and background color of cell
+///
+/// Background is `None` when cell uses default background
+fn resolve_cell_colors(
+    palette: &ColorPalette,
+    attrs: &CellAttributes,
+    bold_is_bright: bool,
+) -> ([f32; 4], Option<[f32; 4]>) {
+    let rgba = |(r, g, b, a): (f32, f32, f32, f32)| [r, g, b, a];
+
+    let foreground = match attrs.foreground() {
+        ColorAttribute::PaletteIndex(index)
+            if bold_is_bright && index < 8 && attrs.intensity() == Intensity::Bold =>
+        {
+            ColorAttribute::PaletteIndex(index + 8)
+        }
+        foreground => foreground,
+    };
+
+    let (fg, bg) = if attrs.reverse() {
+        // default background becomes default foreground
+        (
+            rgba(palette.resolve_bg(attrs.background()).to_tuple_rgba()),
+            Some(rgba(palette.resolve_fg(foreground).to_tuple_rgba())),
+        )
+    } else {
+        (
+            rgba(palette.resolve_fg(foreground).to_tuple_rgba()),
+            match attrs.background() {
+                ColorAttribute::Default => None,
+                bg => Some(rgba(palette.resolve_bg(bg).to_tuple_rgba())),
+            },
+        )
+    };
+
+    let fg = if attrs.intensity() == Intensity::Half {
+        // blend halfway toward background
+        let target = bg.unwrap_or_else(|| rgba(palette.background.to_tuple_rgba()));
+        [
+            (fg[0] + target[0]) * 0.5,
+            (fg[1] + target[1]) * 0.5,
+            (fg[2] + target[2]) * 0.5,
+            fg[3],
+        ]
+    } else {
+        fg
+    };
+
+    (fg, bg)
+}
+
+/// Font metrics, cell size and baseline offset from bottom of cell of monospace font
+///
+/// Extra height from `line_height` is split evenly above and below glyph box
+fn font_metrics(
+    font: FontRef,
+    font_size: f32,
+    line_height: f32,
+    cell_padding: f32,
+) -> (Metrics, [f32; 2], f32) {
+    let metrics = font.metrics(&[]).scale(font_size);
+    // monospace width
+    assert!(metrics.is_monospace);
+    let glyph_metrics = font.glyph_metrics(&[]).scale(font_size);
+    let font_width = glyph_metrics.advance_width(font.charmap().map('M'));
+    let font_height = metrics.ascent + metrics.descent;
+    let cell_height = font_height * line_height.max(1.0);
+    let baseline_offset = metrics.descent + (cell_height - font_height) / 2.0;
+
+    (
+        metrics,
+        [font_width + cell_padding * 2.0, cell_height],
+        baseline_offset,
+    )
+}
+
+/// Logical inner size of window which fits `cols` x `rows` grid, window padding and scrollbar
+pub fn grid_window_size(
+    faces: &FontFaces,
+    font_size: f32,
+    config: &RenderConfig,
+    cols: u32,
+    rows: u32,
+) -> [f32; 2] {
+    let (_, [cell_width, cell_height], _) = font_metrics(
+        faces.regular(),
+        font_size,
+        config.line_height,
+        config.cell_padding,
+    );
+
+    grid_size(
+        [cell_width, cell_height],
+        config.window_padding,
+        scrollbar_width(config.scrollbar_policy, 1.0),
+        cols,
+        rows,
+    )
+}
+
+/// Size of `cols` x `rows` grid with padding on every edge and scrollbar
+fn grid_size(
+    [cell_width, cell_height]: [f32; 2],
+    padding: f32,
+    scrollbar_width: f32,
+    cols: u32,
+    rows: u32,
+) -> [f32; 2] {
+    [
+        (cols as f32 * cell_width + padding * 2.0 + scrollbar_width).ceil(),
+        (rows as f32 * cell_height + padding * 2.0).ceil(),
+    ]
+}
+
+fn max_atlas_layers(device: &wgpu::Device) -> u32 {
+    MAX_ATLAS_LAYERS.min(device.limits().max_texture_array_layers)
+}
+
+/// Rebuild font texture when its atlas is bigger than device can handle
+fn fit_font_texture(device: &wgpu::Device, font_texture: FontTexture) -> FontTexture {
+    let max_texture_width = device.limits().max_texture_dimension_2d;
+    if font_texture.texture_width <= max_texture_width {
+        return font_texture;
     }
+
+    log::info!(
+        "Font texture width {} exceeds device limit {}",
+        font_texture.texture_width,
+        max_texture_width
+    );
+    FontTexture::new(
+        font_texture.faces,
+        font_texture.font_size,
+        max_texture_width,
+    )
+}
+
+fn upload_font_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    font_texture: &FontTexture,
+) -> wgpu::Texture {
+    let texture_width = font_texture.texture_width;
+    let texture = create_font_texture(
+        device,
+        wgpu::TextureFormat::R8Unorm,
+        texture_width,
+        font_texture.layer_count,
+    );
+    queue.write_texture(
+        texture.as_image_copy(),
+        &font_texture.data,
+        wgpu::ImageDataLayout {
+            bytes_per_row: NonZeroU32::new(texture_width),
+            rows_per_image: NonZeroU32::new(texture_width),
+            offset: 0,
+        },
+        wgpu::Extent3d {
+            width: texture_width,
+            height: texture_width,
+            depth_or_array_layers: font_texture.layer_count,
+        },
+    );
+    texture
+}
+
+fn create_color_atlas(
+    device: &wgpu::Device,
+    texture_width: u32,
+) -> (ArrayAllocator, wgpu::Texture) {
+    let mut allocator = ArrayAllocator::new(texture_width, texture_width);
+    allocator.set_max_layer_count(max_atlas_layers(device));
+    let texture = create_font_texture(
+        device,
+        wgpu::TextureFormat::Rgba8Unorm,
+        texture_width,
+        allocator.layer_count(),
+    );
+    (allocator, texture)
+}
+
+fn create_font_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    texture_width: u32,
+    layer_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Font texture"),
+        format,
+        dimension: wgpu::TextureDimension::D2,
+        sample_count: 1,
+        mip_level_count: 1,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_DST
+            | wgpu::TextureUsages::COPY_SRC,
+        size: wgpu::Extent3d {
+            width: texture_width,
+            height: texture_width,
+            depth_or_array_layers: layer_count,
+        },
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    window_size: &WgpuCell<WindowSize>,
+    ui: &WgpuCell<Ui>,
+    texture: &wgpu::Texture,
+    color_texture: &wgpu::Texture,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    let color_texture_view = color_texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("window size bind group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: window_size.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: ui.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: wgpu::BindingResource::TextureView(&color_texture_view),
+            },
+        ],
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u32)]
+pub enum CursorShape {
+    Block = 0,
+    Bar = 1,
+    Underline = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollbarPolicy {
+    Always,
+    /// Show only when there is scrollback
+    ///
+    /// Space of scrollbar is kept while it's hidden, so grid isn't resized whenever it's toggled
+    Auto,
+    /// Never show and use its space for grid
+    Never,
+}
+
+fn scrollbar_width(policy: ScrollbarPolicy, scale_factor: f32) -> f32 {
+    match policy {
+        ScrollbarPolicy::Always | ScrollbarPolicy::Auto => SCROLLBAR_WIDTH * scale_factor,
+        ScrollbarPolicy::Never => 0.0,
+    }
+}
+
+/// Instances of single row
+struct RowInstances {
+    line_no: usize,
+    /// last frame which drew this row
+    frame: u64,
+    glyphs: Vec<GlyphKey>,
+    cells: Vec<CellVertex>,
+    texts: Vec<TextVertex>,
+    rects: Vec<RectVertex>,
+}
+
+impl RowInstances {
+    /// Move instances to other visible line
+    fn move_to(&mut self, line_no: usize, cell_height: f32) {
+        if self.line_no == line_no {
+            return;
+        }
+
+        let delta = line_no as f32 - self.line_no as f32;
+        self.cells.iter_mut().for_each(|v| v.cell_pos[1] += delta);
+        self.texts
+            .iter_mut()
+            .for_each(|v| v.offset[1] += delta * cell_height);
+        self.rects
+            .iter_mut()
+            .for_each(|v| v.position[1] += delta * cell_height);
+        self.line_no = line_no;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ShapedGlyph {
+    id: u16,
+    /// pixel x and offset y from baseline
+    position: [f32; 2],
+    color: [f32; 3],
+    blink: bool,
 }
 
 #[repr(C)]
@@ -568,6 +2264,17 @@ struct TextVertex {
     tex_size: [f32; 2],
     color: [f32; 3],
     layer: i32,
+    is_color: u32,
+    /// hidden while text blink phase is off
+    blink: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct RectVertex {
+    color: [f32; 4],
+    position: [f32; 2],
+    size: [f32; 2],
 }
 
 #[repr(C)]
@@ -576,7 +2283,14 @@ struct WindowSize {
     size: [f32; 2],
     cell_size: [f32; 2],
     column: u32,
-    pad: u32,
+    texture_width: f32,
+    text_contrast: f32,
+    /// surface blends in linear space
+    srgb: u32,
+    column_offset: u32,
+    /// physical pixels between window edge and grid
+    padding: f32,
+    pad: [u32; 2],
 }
 
 #[repr(C)]
@@ -589,7 +2303,11 @@ struct Ui {
     scrollbar_fg: [f32; 4],
     scrollbar_bg: [f32; 4],
     scrollbar_top: f32,
-    pad: [f32; 3],
+    cursor_shape: u32,
+    cursor_hollow: u32,
+    cursor_visible: u32,
+    text_visible: u32,
+    pad: [u32; 3],
 }
 
 impl Ui {
@@ -609,8 +2327,20 @@ impl Ui {
     }
 }
 
-static_assertions::assert_eq_size!(Ui, [f32; 20]);
-static_assertions::assert_eq_size!(WindowSize, [u8; 24]);
+static_assertions::assert_eq_size!(Ui, [f32; 24]);
+static_assertions::assert_eq_size!(WindowSize, [u8; 48]);
+
+#[derive(Clone, PartialEq)]
+enum HoveredLink {
+    /// OSC 8 hyperlink, may span multiple rows
+    Hyperlink(Arc<Hyperlink>),
+    /// url found in plain text
+    Detected {
+        row: StableRowIndex,
+        cols: Range<usize>,
+        url: String,
+    },
+}
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum MouseTarget {
@@ -625,6 +2355,8 @@ enum MouseStatus {
         target: MouseTarget,
         start: (f32, f32),
         current: (f32, f32),
+        /// scroll offset when drag started
+        start_offset: StableRowIndex,
     },
 }
 
@@ -633,3 +2365,254 @@ impl Default for MouseStatus {
         Self::Hover(MouseTarget::Empty)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use termwiz::color::RgbColor;
+
+    /// (name, offset) of members and size of struct declared in shader
+    fn wgsl_struct(name: &str) -> (Vec<(String, u32)>, u32) {
+        let module = naga::front::wgsl::parse_str(include_str!("../shaders/shader.wgsl")).unwrap();
+        let layout = module
+            .types
+            .iter()
+            .find_map(|(_, ty)| match ty.inner {
+                naga::TypeInner::Struct { ref members, span }
+                    if ty.name.as_deref() == Some(name) =>
+                {
+                    let members = members
+                        .iter()
+                        .map(|member| (member.name.clone().unwrap_or_default(), member.offset))
+                        .collect();
+                    Some((members, span))
+                }
+                _ => None,
+            })
+            .unwrap_or_else(|| panic!("{} is not in shader", name));
+        layout
+    }
+
+    /// (name, offset) of fields which shader reads, padding is left out
+    macro_rules! field_offsets {
+        ($ty:ident { $($field:ident),* $(,)? }) => {
+            vec![$((
+                stringify!($field).to_string(),
+                bytemuck::offset_of!($ty::zeroed(), $ty, $field) as u32,
+            )),*]
+        };
+    }
+
+    #[test]
+    fn grid_window_size_includes_padding() {
+        use crate::render::{FONT, FONT_SIZE};
+
+        let faces = FontFaces::new(FontRef::from_index(FONT, 0).unwrap());
+        let config = RenderConfig {
+            window_padding: 6.0,
+            ..Default::default()
+        };
+        // what `grid_pixel_size` computes from at scale factor 1.0
+        let (_, cell_size, _) = font_metrics(
+            faces.regular(),
+            FONT_SIZE,
+            config.line_height,
+            config.cell_padding,
+        );
+        let scrollbar = scrollbar_width(config.scrollbar_policy, 1.0);
+        let [width, height] = grid_window_size(&faces, FONT_SIZE, &config, 80, 24);
+
+        assert_eq!(
+            [width, height],
+            grid_size(cell_size, config.window_padding, scrollbar, 80, 24)
+        );
+        assert_eq!(
+            [width, height],
+            [
+                (80.0 * cell_size[0] + 12.0 + scrollbar).ceil(),
+                (24.0 * cell_size[1] + 12.0).ceil(),
+            ]
+        );
+    }
+
+    #[test]
+    fn window_size_matches_shader_layout() {
+        let (members, span) = wgsl_struct("WindowSizeUniform");
+        let fields = field_offsets!(WindowSize {
+            size,
+            cell_size,
+            column,
+            texture_width,
+            text_contrast,
+            srgb,
+            column_offset,
+            padding,
+        });
+
+        assert_eq!(members, fields);
+        assert!(span as usize <= mem::size_of::<WindowSize>());
+    }
+
+    #[test]
+    fn ui_matches_shader_layout() {
+        let (members, span) = wgsl_struct("UiUniform");
+        let fields = field_offsets!(Ui {
+            cursor_color,
+            cursor_pos,
+            scrollbar_width,
+            scrollbar_height,
+            scrollbar_fg,
+            scrollbar_bg,
+            scrollbar_top,
+            cursor_shape,
+            cursor_hollow,
+            cursor_visible,
+            text_visible,
+        });
+
+        assert_eq!(members, fields);
+        assert!(span as usize <= mem::size_of::<Ui>());
+    }
+
+    fn palette() -> ColorPalette {
+        ColorPalette {
+            foreground: RgbColor::new_8bpc(255, 255, 255),
+            background: RgbColor::new_8bpc(0, 0, 0),
+            ..Default::default()
+        }
+    }
+
+    fn rgba(color: (f32, f32, f32, f32)) -> [f32; 4] {
+        [color.0, color.1, color.2, color.3]
+    }
+
+    #[test]
+    fn pixel_to_grid_subtracts_padding() {
+        let cell_size = [10.0, 20.0];
+
+        assert_eq!(pixel_to_grid([0.0, 0.0], 0.0, cell_size, (80, 24)), (0, 0));
+        assert_eq!(
+            pixel_to_grid([19.9, 39.9], 0.0, cell_size, (80, 24)),
+            (1, 1)
+        );
+        // 5px padding moves every boundary
+        assert_eq!(
+            pixel_to_grid([14.9, 24.9], 5.0, cell_size, (80, 24)),
+            (0, 0)
+        );
+        assert_eq!(
+            pixel_to_grid([15.0, 25.0], 5.0, cell_size, (80, 24)),
+            (1, 1)
+        );
+        // padding itself maps to edge cells
+        assert_eq!(pixel_to_grid([2.0, 2.0], 5.0, cell_size, (80, 24)), (0, 0));
+        assert_eq!(
+            pixel_to_grid([-3.0, -3.0], 5.0, cell_size, (80, 24)),
+            (0, 0)
+        );
+        assert_eq!(
+            pixel_to_grid([810.0, 490.0], 5.0, cell_size, (80, 24)),
+            (79, 23)
+        );
+    }
+
+    #[test]
+    fn mixed_width_cells_keep_columns() {
+        let attrs = CellAttributes::default();
+        let cells = vec![
+            Cell::new('a', attrs.clone()),
+            Cell::new('漢', attrs.clone()),
+            Cell::blank(),
+            Cell::new('b', attrs.clone()),
+            Cell::new('字', attrs.clone()),
+            Cell::blank(),
+            Cell::new('c', attrs),
+        ];
+
+        let columns = columned_cells(&cells)
+            .into_iter()
+            .map(|(column, cell)| (column, cell.str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            columns,
+            [(0, "a"), (1, "漢"), (3, "b"), (4, "字"), (6, "c")]
+        );
+    }
+
+    #[test]
+    fn default_colors() {
+        let palette = palette();
+        let (fg, bg) = resolve_cell_colors(&palette, &CellAttributes::default(), false);
+
+        assert_eq!(fg, rgba(palette.foreground.to_tuple_rgba()));
+        assert_eq!(bg, None);
+    }
+
+    #[test]
+    fn reverse_swaps_default_colors() {
+        let palette = palette();
+        let mut attrs = CellAttributes::default();
+        attrs.set_reverse(true);
+
+        let (fg, bg) = resolve_cell_colors(&palette, &attrs, false);
+
+        assert_eq!(fg, rgba(palette.background.to_tuple_rgba()));
+        assert_eq!(bg, Some(rgba(palette.foreground.to_tuple_rgba())));
+    }
+
+    #[test]
+    fn reverse_swaps_palette_colors() {
+        let palette = palette();
+        let mut attrs = CellAttributes::default();
+        attrs
+            .set_foreground(ColorAttribute::PaletteIndex(1))
+            .set_background(ColorAttribute::PaletteIndex(4))
+            .set_reverse(true);
+
+        let (fg, bg) = resolve_cell_colors(&palette, &attrs, false);
+
+        assert_eq!(fg, rgba(palette.colors.0[4].to_tuple_rgba()));
+        assert_eq!(bg, Some(rgba(palette.colors.0[1].to_tuple_rgba())));
+    }
+
+    #[test]
+    fn dim_blends_toward_background() {
+        let palette = palette();
+        let mut attrs = CellAttributes::default();
+        attrs.set_intensity(Intensity::Half);
+
+        let (fg, bg) = resolve_cell_colors(&palette, &attrs, false);
+
+        assert_eq!(fg, [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(bg, None);
+    }
+
+    #[test]
+    fn dim_reverse_blends_toward_reversed_background() {
+        let palette = palette();
+        let mut attrs = CellAttributes::default();
+        attrs.set_intensity(Intensity::Half).set_reverse(true);
+
+        let (fg, bg) = resolve_cell_colors(&palette, &attrs, false);
+
+        assert_eq!(fg, [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(bg, Some([1.0; 4]));
+    }
+
+    #[test]
+    fn bold_is_bright() {
+        let palette = palette();
+        let mut attrs = CellAttributes::default();
+        attrs
+            .set_foreground(ColorAttribute::PaletteIndex(1))
+            .set_intensity(Intensity::Bold);
+
+        let (bright, _) = resolve_cell_colors(&palette, &attrs, true);
+        let (normal, _) = resolve_cell_colors(&palette, &attrs, false);
+
+        assert_eq!(bright, rgba(palette.colors.0[9].to_tuple_rgba()));
+        assert_eq!(normal, rgba(palette.colors.0[1].to_tuple_rgba()));
+    }
+}
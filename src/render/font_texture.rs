@@ -1,81 +1,297 @@
+use std::{fmt, path::Path, time::Instant};
+
 use ahash::AHashMap;
+use rayon::prelude::*;
 use swash::{
-    scale::{image::Image, Render, ScaleContext, Source, StrikeWith},
+    scale::{
+        image::{Content, Image},
+        Render, ScaleContext, Source, StrikeWith,
+    },
+    zeno::Placement,
     FontRef,
 };
 
-use crate::render::atlas::ArrayAllocator;
+use crate::render::atlas::{Allocation, ArrayAllocator};
+
+use super::{MAX_TEXTURE_WIDTH, MIN_TEXTURE_WIDTH};
+
+/// Index of font face and glyph id
+pub type GlyphKey = (usize, u16);
+
+const REGULAR_FACE: usize = 0;
+const BOLD_FACE: usize = 1;
+const ITALIC_FACE: usize = 2;
+const BOLD_ITALIC_FACE: usize = 3;
+/// Index of first fallback face
+const FALLBACK_FACE: usize = 4;
+
+#[derive(Debug)]
+pub enum FontError {
+    Io(std::io::Error),
+    /// File is not a font swash can read
+    Invalid,
+    /// Cell grid needs same advance for every glyph
+    NotMonospace,
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontError::Io(err) => write!(f, "Failed to read font: {}", err),
+            FontError::Invalid => f.write_str("Invalid font file"),
+            FontError::NotMonospace => f.write_str("Font is not monospace"),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+impl From<std::io::Error> for FontError {
+    fn from(err: std::io::Error) -> Self {
+        FontError::Io(err)
+    }
+}
+
+/// Load monospace font from file
+pub fn load_font(path: &Path) -> Result<FontRef<'static>, FontError> {
+    load_font_data(std::fs::read(path)?)
+}
+
+/// Fonts loaded at runtime, data of same font is leaked only once however many times it's reloaded
+#[derive(Default)]
+pub struct FontCache {
+    fonts: Vec<FontRef<'static>>,
+}
+
+impl FontCache {
+    /// Load monospace font from file
+    pub fn load(&mut self, path: &Path) -> Result<FontRef<'static>, FontError> {
+        let data = std::fs::read(path)?;
+        if let Some(&font) = self.fonts.iter().find(|font| font.data == data.as_slice()) {
+            return Ok(font);
+        }
+
+        let font = load_font_data(data)?;
+        self.fonts.push(font);
+        Ok(font)
+    }
+}
+
+/// Load monospace font from memory
+///
+/// Font data is leaked since it lives until program exits
+pub fn load_font_data(data: Vec<u8>) -> Result<FontRef<'static>, FontError> {
+    let font = FontRef::from_index(&data, 0).ok_or(FontError::Invalid)?;
+    if !font.metrics(&[]).is_monospace {
+        return Err(FontError::NotMonospace);
+    }
+
+    let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+    Ok(FontRef::from_index(data, 0).unwrap())
+}
+
+/// Load font from file, fallback font doesn't need to be monospace
+///
+/// Font data is leaked since it lives until program exits
+pub fn load_fallback_font(path: &Path) -> Result<FontRef<'static>, FontError> {
+    let data = std::fs::read(path)?;
+    if FontRef::from_index(&data, 0).is_none() {
+        return Err(FontError::Invalid);
+    }
+
+    let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+    Ok(FontRef::from_index(data, 0).unwrap())
+}
+
+/// Regular, bold, italic, bold italic font faces and fallback faces
+///
+/// Missing variant falls back to regular
+#[derive(Clone)]
+pub struct FontFaces {
+    faces: Vec<Option<FontRef<'static>>>,
+}
+
+impl FontFaces {
+    pub fn new(regular: FontRef<'static>) -> Self {
+        Self {
+            faces: vec![Some(regular), None, None, None],
+        }
+    }
+
+    /// Fallback is used for characters missing in style faces, in added order
+    pub fn with_fallback(mut self, font: FontRef<'static>) -> Self {
+        self.faces.push(Some(font));
+        self
+    }
 
-use super::{TEXTURE_SIZE, TEXTURE_WIDTH};
+    /// Replace regular face, style faces of previous font are dropped but fallbacks are kept
+    pub fn with_regular(mut self, font: FontRef<'static>) -> Self {
+        self.faces[REGULAR_FACE] = Some(font);
+        self.faces[BOLD_FACE] = None;
+        self.faces[ITALIC_FACE] = None;
+        self.faces[BOLD_ITALIC_FACE] = None;
+        self
+    }
+
+    pub fn with_bold(mut self, font: FontRef<'static>) -> Self {
+        self.faces[BOLD_FACE] = Some(font);
+        self
+    }
+
+    pub fn with_italic(mut self, font: FontRef<'static>) -> Self {
+        self.faces[ITALIC_FACE] = Some(font);
+        self
+    }
+
+    pub fn with_bold_italic(mut self, font: FontRef<'static>) -> Self {
+        self.faces[BOLD_ITALIC_FACE] = Some(font);
+        self
+    }
+
+    pub fn regular(&self) -> FontRef<'static> {
+        self.faces[REGULAR_FACE].unwrap()
+    }
+
+    /// Get face index for style
+    pub fn face_id(&self, bold: bool, italic: bool) -> usize {
+        let face = match (bold, italic) {
+            (false, false) => REGULAR_FACE,
+            (true, false) => BOLD_FACE,
+            (false, true) => ITALIC_FACE,
+            (true, true) => BOLD_ITALIC_FACE,
+        };
+
+        if self.faces[face].is_some() {
+            face
+        } else {
+            REGULAR_FACE
+        }
+    }
+
+    pub fn get(&self, face: usize) -> FontRef<'static> {
+        self.faces[face].unwrap_or_else(|| self.regular())
+    }
+
+    /// Get `face` if it has glyph for `c`, otherwise first fallback face which has it
+    pub fn face_for_char(&self, face: usize, c: char) -> usize {
+        if self.get(face).charmap().map(c) != 0 {
+            return face;
+        }
+
+        (FALLBACK_FACE..self.faces.len())
+            .find(|&fallback| self.get(fallback).charmap().map(c) != 0)
+            .unwrap_or(face)
+    }
+
+    pub fn is_fallback(&self, face: usize) -> bool {
+        face >= FALLBACK_FACE
+    }
+
+    /// Style faces, fallback faces are rasterized lazily
+    fn iter(&self) -> impl Iterator<Item = (usize, FontRef<'static>)> + '_ {
+        self.faces[..FALLBACK_FACE]
+            .iter()
+            .enumerate()
+            .filter_map(|(face, font)| Some((face, (*font)?)))
+    }
+}
 
 pub struct FontTexture {
-    pub font: FontRef<'static>,
+    pub faces: FontFaces,
+    pub font_size: f32,
+    /// Width and height of each layer
+    pub texture_width: u32,
     pub data: Vec<u8>,
-    pub glyph_cache: AHashMap<u16, GlyphCacheInfo>,
+    /// `None` means glyph has nothing to draw
+    pub glyph_cache: AHashMap<GlyphKey, Option<GlyphCacheInfo>>,
     pub layer_count: u32,
+    pub allocator: ArrayAllocator,
+    pub rasterizer: GlyphRasterizer,
 }
 
 impl FontTexture {
-    pub fn new(font: FontRef<'static>, font_size: f32) -> Self {
-        let mut allocator = ArrayAllocator::new(TEXTURE_WIDTH, TEXTURE_WIDTH);
+    /// `max_texture_width` should be `max_texture_dimension_2d` of device
+    pub fn new(faces: FontFaces, font_size: f32, max_texture_width: u32) -> Self {
+        let texture_width = atlas_width(font_size, max_texture_width);
+        let texture_size = (texture_width * texture_width) as usize;
+        let mut allocator = ArrayAllocator::new(texture_width, texture_width);
 
         let mut glyph_cache = AHashMap::new();
-        let mut scale_ctx = ScaleContext::new();
-        let mut image = Image::new();
-        let mut data = vec![0; TEXTURE_SIZE * 2];
-        let mut layer_count = 1;
+        let rasterizer = GlyphRasterizer::new();
+        let mut data = vec![0; texture_size * allocator.layer_count() as usize];
+
+        // only printable ascii is prepared, other glyphs are rasterized lazily
+        let mut glyphs = Vec::new();
+        for (face, font) in faces.iter() {
+            for c in ' '..='~' {
+                let id = font.charmap().map(c);
+                if !glyphs.iter().any(|&(f, _, i)| (f, i) == (face, id)) {
+                    glyphs.push((face, font, id));
+                }
+            }
+        }
 
-        let mut scaler = scale_ctx.builder(font).hint(true).size(font_size).build();
+        let start = Instant::now();
+        // rasterizing is independent for each glyph, only packing into atlas is sequential
+        let images: Vec<Option<Image>> = {
+            profiling::scope!("Rasterize glyphs");
+            glyphs
+                .par_iter()
+                .map_init(GlyphRasterizer::new, |rasterizer, &(_, font, id)| {
+                    rasterizer
+                        .rasterize(font, font_size, id)
+                        .then(|| rasterizer.image().clone())
+                })
+                .collect()
+        };
+        log::debug!(
+            "Rasterized {} glyphs in {:?}",
+            glyphs.len(),
+            start.elapsed()
+        );
 
-        {
+        for (&(face, _, id), image) in glyphs.iter().zip(images.iter()) {
             profiling::scope!("Create font texture");
 
-            font.charmap().enumerate(|_c, id| {
-                image.clear();
-                if Render::new(&[
-                    Source::ColorBitmap(StrikeWith::BestFit),
-                    Source::ColorOutline(0),
-                    Source::Bitmap(StrikeWith::BestFit),
-                    Source::Outline,
-                ])
-                .render_into(&mut scaler, id, &mut image)
-                {
-                    if image.placement.width == 0 || image.placement.height == 0 {
-                    } else {
-                        let alloc = allocator.alloc(image.placement.width, image.placement.height);
-                        if let Some(new_page) = alloc.layer.checked_sub(layer_count) {
-                            data.extend(
-                                std::iter::repeat(0).take(TEXTURE_SIZE * new_page as usize),
-                            );
-                            layer_count += new_page;
-                        }
-                        let page = &mut data[TEXTURE_SIZE * alloc.layer as usize..][..TEXTURE_SIZE];
-                        let left_top = (alloc.y * TEXTURE_WIDTH + alloc.x) as usize;
-
-                        for (row_index, row) in image
-                            .data
-                            .chunks_exact(image.placement.width as usize)
-                            .enumerate()
-                        {
-                            let begin = left_top + row_index * TEXTURE_WIDTH as usize;
-                            let end = begin + row.len();
-                            page[begin..end].copy_from_slice(row);
-                        }
-                        glyph_cache.insert(
-                            id,
-                            GlyphCacheInfo {
-                                tex_position: [alloc.x as _, alloc.y as _],
-                                tex_size: [image.placement.width as _, image.placement.height as _],
-                                glyph_position: [
-                                    image.placement.left as _,
-                                    image.placement.top as _,
-                                ],
-                                layer: alloc.layer as _,
-                            },
-                        );
-                    }
+            let image = match image {
+                Some(image) => image,
+                None => {
+                    glyph_cache.insert((face, id), None);
+                    continue;
                 }
-            });
+            };
+            // color glyphs go to color atlas lazily
+            if image.content == Content::Color {
+                continue;
+            }
+            let alloc = match allocator.alloc(image.placement.width, image.placement.height) {
+                Ok(alloc) => alloc,
+                Err(err) => {
+                    log::warn!("Skip glyph {}: {}", id, err);
+                    glyph_cache.insert((face, id), None);
+                    continue;
+                }
+            };
+            let needed = texture_size * allocator.layer_count() as usize;
+            if data.len() < needed {
+                data.resize(needed, 0);
+            }
+            let page = &mut data[texture_size * alloc.layer as usize..][..texture_size];
+            let left_top = (alloc.y * texture_width + alloc.x) as usize;
+
+            for (row_index, row) in image
+                .data
+                .chunks_exact(image.placement.width as usize)
+                .enumerate()
+            {
+                let begin = left_top + row_index * texture_width as usize;
+                let end = begin + row.len();
+                page[begin..end].copy_from_slice(row);
+            }
+            glyph_cache.insert(
+                (face, id),
+                Some(GlyphCacheInfo::new(alloc, image.placement, false)),
+            );
         }
 
         // use std::io::Write;
@@ -84,22 +300,96 @@ impl FontTexture {
         //     .create(true)
         //     .open("foo.pgm")
         //     .unwrap();
-        // write!(out, "P5\n{} {}\n255\n", TEXTURE_WIDTH, TEXTURE_WIDTH).unwrap();
-        // out.write_all(&data[..TEXTURE_SIZE]).unwrap();
+        // write!(out, "P5\n{} {}\n255\n", texture_width, texture_width).unwrap();
+        // out.write_all(&data[..texture_size]).unwrap();
         // out.flush().unwrap();
 
         Self {
-            font,
+            faces,
+            font_size,
+            texture_width,
             data,
             glyph_cache,
             layer_count: allocator.layer_count(),
+            allocator,
+            rasterizer,
         }
     }
 }
 
+/// Bigger font needs bigger atlas layer, it's clamped to device limit
+pub fn atlas_width(font_size: f32, max_texture_width: u32) -> u32 {
+    let max = max_texture_width.min(MAX_TEXTURE_WIDTH);
+    ((font_size * 64.0) as u32)
+        .next_power_of_two()
+        .max(MIN_TEXTURE_WIDTH)
+        .min(max)
+}
+
+pub struct GlyphRasterizer {
+    scale_ctx: ScaleContext,
+    image: Image,
+}
+
+impl GlyphRasterizer {
+    pub fn new() -> Self {
+        Self {
+            scale_ctx: ScaleContext::new(),
+            image: Image::new(),
+        }
+    }
+
+    /// Rasterize glyph into inner image, returns `false` when glyph has nothing to draw
+    pub fn rasterize(&mut self, font: FontRef, font_size: f32, id: u16) -> bool {
+        self.image.clear();
+        let mut scaler = self
+            .scale_ctx
+            .builder(font)
+            .hint(true)
+            .size(font_size)
+            .build();
+
+        Render::new(&[
+            Source::ColorBitmap(StrikeWith::BestFit),
+            Source::ColorOutline(0),
+            Source::Bitmap(StrikeWith::BestFit),
+            Source::Outline,
+        ])
+        .render_into(&mut scaler, id, &mut self.image)
+            && self.image.placement.width != 0
+            && self.image.placement.height != 0
+    }
+
+    /// Last rasterized image
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct GlyphCacheInfo {
     pub tex_position: [f32; 2],
     pub glyph_position: [f32; 2],
     pub tex_size: [f32; 2],
     pub layer: i32,
+    /// Glyph is stored in color atlas
+    pub is_color: bool,
+    /// Atlas region, freed when glyph is evicted
+    pub alloc: Allocation,
+    /// Last frame which drew this glyph
+    pub last_used: u64,
+}
+
+impl GlyphCacheInfo {
+    pub fn new(alloc: Allocation, placement: Placement, is_color: bool) -> Self {
+        Self {
+            tex_position: [alloc.x as _, alloc.y as _],
+            tex_size: [placement.width as _, placement.height as _],
+            glyph_position: [placement.left as _, placement.top as _],
+            layer: alloc.layer as _,
+            is_color,
+            alloc,
+            last_used: 0,
+        }
+    }
 }
@@ -0,0 +1,342 @@
+use std::num::NonZeroU32;
+
+use bytemuck::{Pod, Zeroable};
+use termwiz::{
+    escape::osc::{ITermDimension, ITermFileData},
+    surface::SequenceNo,
+};
+use wezterm_term::{StableRowIndex, Terminal};
+use wgpu::SamplerBindingType;
+use wgpu_container::WgpuVec;
+
+/// Oldest image is dropped over this to bound gpu memory
+const MAX_IMAGES: usize = 64;
+
+/// Inline images of iTerm2 `OSC 1337 ; File=inline=1`
+pub struct ImageContext {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    placements: Vec<Placement>,
+    instances: WgpuVec<ImageVertex>,
+    /// placement of each instance
+    visible: Vec<usize>,
+}
+
+/// Image anchored at stable row so it scrolls with text
+struct Placement {
+    row: StableRowIndex,
+    col: usize,
+    /// px
+    size: [f32; 2],
+    alt_screen: bool,
+    /// image is gone when its first row is changed after this
+    ///
+    /// `None` until terminal writes image cells
+    seqno: Option<SequenceNo>,
+    bind_group: wgpu::BindGroup,
+    _texture: wgpu::Texture,
+}
+
+impl ImageContext {
+    pub fn new(
+        device: &wgpu::Device,
+        cell_bind_group_layout: &wgpu::BindGroupLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("image_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[cell_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("image_pipeline"),
+            multiview: None,
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "image_vs",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<ImageVertex>() as _,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                    ],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "image_fs",
+                targets: &[wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                front_face: wgpu::FrontFace::Cw,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+        });
+
+        // images are scaled to cells unlike glyphs
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            placements: Vec::new(),
+            instances: WgpuVec::new(device, wgpu::BufferUsages::VERTEX),
+            visible: Vec::new(),
+        }
+    }
+
+    /// Decode image and place it at cursor, should be called before terminal performs the sequence
+    ///
+    /// `window_size` is used for percent dimensions
+    pub fn add(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        term: &Terminal,
+        file: &ITermFileData,
+        cell_size: [f32; 2],
+        window_size: [f32; 2],
+    ) {
+        let image = match image::load_from_memory(&file.data) {
+            Ok(image) => image.to_rgba8(),
+            Err(err) => {
+                log::warn!("Failed to decode inline image {:?}: {}", file.name, err);
+                return;
+            }
+        };
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("inline_image"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            &image,
+            wgpu::ImageDataLayout {
+                bytes_per_row: NonZeroU32::new(width * 4),
+                rows_per_image: NonZeroU32::new(height),
+                offset: 0,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("image_bind_group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let size = image_size(file, [width as f32, height as f32], cell_size, window_size);
+        let cursor = term.cursor_pos();
+        let screen = term.screen();
+
+        if self.placements.len() >= MAX_IMAGES {
+            self.placements.remove(0);
+        }
+        self.placements.push(Placement {
+            row: screen.visible_row_to_stable_row(cursor.y),
+            col: cursor.x,
+            size,
+            alt_screen: term.is_alt_screen_active(),
+            seqno: None,
+            bind_group,
+            _texture: texture,
+        });
+    }
+
+    /// Rebuild instances of visible images
+    ///
+    /// Images overwritten or evicted from scrollback are dropped
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        term: &Terminal,
+        scroll_offset: StableRowIndex,
+        cell_size: [f32; 2],
+    ) {
+        let screen = term.screen();
+        let first_row = screen.phys_to_stable_row_index(0);
+        let alt_screen = term.is_alt_screen_active();
+        let current_seqno = term.current_seqno();
+
+        for placement in self.placements.iter_mut() {
+            if placement.alt_screen == alt_screen && placement.seqno.is_none() {
+                placement.seqno = Some(current_seqno);
+            }
+        }
+
+        self.placements.retain(|placement| {
+            if placement.alt_screen != alt_screen {
+                return true;
+            }
+            match screen
+                .stable_row_to_phys(placement.row)
+                .and_then(|phys| screen.lines.get(phys))
+            {
+                Some(line) => placement
+                    .seqno
+                    .map_or(true, |seqno| !line.changed_since(seqno)),
+                // evicted from scrollback
+                None => placement.row >= first_row,
+            }
+        });
+
+        self.instances.clear();
+        self.visible.clear();
+        let [cell_width, cell_height] = cell_size;
+        let rows = screen.physical_rows as StableRowIndex;
+
+        for (index, placement) in self.placements.iter().enumerate() {
+            let image_rows = (placement.size[1] / cell_height).ceil() as StableRowIndex;
+            let visible = placement.alt_screen == alt_screen
+                && placement.row < scroll_offset + rows
+                && placement.row + image_rows > scroll_offset;
+            if !visible {
+                continue;
+            }
+
+            self.instances.push(ImageVertex {
+                position: [
+                    placement.col as f32 * cell_width,
+                    (placement.row - scroll_offset) as f32 * cell_height,
+                ],
+                size: placement.size,
+            });
+            self.visible.push(index);
+        }
+
+        self.instances.write_dirty(device, queue);
+    }
+
+    /// Cell bind group should be set at group 0
+    pub fn draw<'a>(&'a self, rpass: &mut wgpu::RenderPass<'a>) {
+        if self.instances.is_empty() {
+            return;
+        }
+
+        rpass.push_debug_group("Draw image");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_vertex_buffer(0, self.instances.gpu_buffer().slice(..));
+        for (instance, &index) in self.visible.iter().enumerate() {
+            let instance = instance as u32;
+            rpass.set_bind_group(1, &self.placements[index].bind_group, &[]);
+            rpass.draw(0..4, instance..instance + 1);
+        }
+        rpass.pop_debug_group();
+    }
+}
+
+/// Pixel size of image from requested dimensions
+fn image_size(
+    file: &ITermFileData,
+    [image_width, image_height]: [f32; 2],
+    [cell_width, cell_height]: [f32; 2],
+    [window_width, window_height]: [f32; 2],
+) -> [f32; 2] {
+    let dimension = |dim: &ITermDimension, image: f32, cell: f32, window: f32| match *dim {
+        ITermDimension::Automatic => image,
+        ITermDimension::Cells(n) => n as f32 * cell,
+        ITermDimension::Pixels(n) => n as f32,
+        ITermDimension::Percent(n) => n as f32 * window / 100.0,
+    };
+    let width = dimension(&file.width, image_width, cell_width, window_width);
+    let height = dimension(&file.height, image_height, cell_height, window_height);
+
+    if !file.preserve_aspect_ratio {
+        return [width, height];
+    }
+
+    match (&file.width, &file.height) {
+        (ITermDimension::Automatic, ITermDimension::Automatic) => [width, height],
+        (_, ITermDimension::Automatic) => [width, width * image_height / image_width],
+        (ITermDimension::Automatic, _) => [height * image_width / image_height, height],
+        // fit in requested box
+        _ => {
+            let scale = (width / image_width).min(height / image_height);
+            [image_width * scale, image_height * scale]
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ImageVertex {
+    /// px
+    position: [f32; 2],
+    /// px
+    size: [f32; 2],
+}
@@ -0,0 +1,242 @@
+use std::ops::{Range, RangeInclusive};
+
+use wezterm_term::{StableRowIndex, Terminal};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionMode {
+    /// Follows text from start to end, rows between them are fully selected
+    Cell,
+    /// Rectangle between anchor and active, dragged with alt
+    Block,
+}
+
+/// Cells selected with mouse, (row, column) positions are inclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    /// where dragging started
+    pub anchor: (StableRowIndex, usize),
+    /// follows mouse while dragging
+    pub active: (StableRowIndex, usize),
+    pub mode: SelectionMode,
+}
+
+impl Selection {
+    pub fn new(cell: (StableRowIndex, usize), mode: SelectionMode) -> Self {
+        Self {
+            anchor: cell,
+            active: cell,
+            mode,
+        }
+    }
+
+    /// Start and end of selection, end is inclusive
+    ///
+    /// For block selection they are top left and bottom right corner
+    pub fn normalize(&self) -> ((StableRowIndex, usize), (StableRowIndex, usize)) {
+        let (anchor, active) = (self.anchor, self.active);
+        match self.mode {
+            SelectionMode::Cell => {
+                if anchor <= active {
+                    (anchor, active)
+                } else {
+                    (active, anchor)
+                }
+            }
+            SelectionMode::Block => (
+                (anchor.0.min(active.0), anchor.1.min(active.1)),
+                (anchor.0.max(active.0), anchor.1.max(active.1)),
+            ),
+        }
+    }
+
+    pub fn rows(&self) -> RangeInclusive<StableRowIndex> {
+        let (start, end) = self.normalize();
+        start.0..=end.0
+    }
+
+    /// Selected columns of row, clamped to `cols`
+    pub fn columns(&self, row: StableRowIndex, cols: usize) -> Range<usize> {
+        let (start, end) = self.normalize();
+        if !self.rows().contains(&row) {
+            return 0..0;
+        }
+
+        let (begin, end) = match self.mode {
+            SelectionMode::Cell => {
+                let begin = if row == start.0 { start.1 } else { 0 };
+                let end = if row == end.0 { end.1 + 1 } else { cols };
+                (begin, end)
+            }
+            SelectionMode::Block => (start.1, end.1 + 1),
+        };
+
+        begin.min(cols)..end.min(cols)
+    }
+
+    pub fn contains(&self, row: StableRowIndex, col: usize) -> bool {
+        self.columns(row, usize::MAX).contains(&col)
+    }
+
+    /// Get selected text, trailing whitespace of each line is trimmed
+    ///
+    /// Rows wrapped by terminal are joined without newline unless it's block selection
+    pub fn text(&self, term: &Terminal) -> String {
+        let screen = term.screen();
+        let rows = self.rows();
+        let last = *rows.end();
+        let mut text = String::new();
+
+        for row in rows {
+            let line = match screen
+                .stable_row_to_phys(row)
+                .and_then(|phys| screen.lines.get(phys))
+            {
+                Some(line) => line,
+                None => continue,
+            };
+            let cells = line.cells();
+            let line_start = text.len();
+
+            for cell in cells
+                .get(self.columns(row, cells.len()))
+                .unwrap_or_default()
+            {
+                text.push_str(cell.str());
+            }
+
+            // logical line continues on next row, its trailing spaces are content
+            if row != last && self.mode == SelectionMode::Cell && line.last_cell_was_wrapped() {
+                continue;
+            }
+
+            text.truncate(line_start + text[line_start..].trim_end().len());
+
+            if row != last {
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use wezterm_term::TerminalSize;
+
+    use crate::term::TerminalConfig;
+
+    fn terminal(cols: usize, rows: usize, text: &str) -> Terminal {
+        let mut term = Terminal::new(
+            TerminalSize {
+                physical_cols: cols,
+                physical_rows: rows,
+                pixel_height: 0,
+                pixel_width: 0,
+            },
+            Arc::new(TerminalConfig::new([0.0; 4], [1.0; 4], 0, None)),
+            "temu",
+            "0.1.0",
+            Box::new(std::io::sink()),
+        );
+        term.advance_bytes(text);
+        term
+    }
+
+    fn selection(
+        anchor: (StableRowIndex, usize),
+        active: (StableRowIndex, usize),
+        mode: SelectionMode,
+    ) -> Selection {
+        Selection {
+            anchor,
+            active,
+            mode,
+        }
+    }
+
+    #[test]
+    fn normalize_cell_orders_by_row_then_column() {
+        let sel = selection((3, 2), (1, 5), SelectionMode::Cell);
+        assert_eq!(sel.normalize(), ((1, 5), (3, 2)));
+
+        let sel = selection((1, 5), (1, 2), SelectionMode::Cell);
+        assert_eq!(sel.normalize(), ((1, 2), (1, 5)));
+    }
+
+    #[test]
+    fn normalize_block_takes_corners() {
+        let sel = selection((3, 2), (1, 5), SelectionMode::Block);
+        assert_eq!(sel.normalize(), ((1, 2), (3, 5)));
+    }
+
+    #[test]
+    fn rows_are_inclusive() {
+        let sel = selection((4, 0), (2, 7), SelectionMode::Cell);
+        assert_eq!(sel.rows(), 2..=4);
+
+        let sel = Selection::new((5, 3), SelectionMode::Cell);
+        assert_eq!(sel.rows(), 5..=5);
+    }
+
+    #[test]
+    fn contains_cell_selection() {
+        let sel = selection((1, 5), (3, 2), SelectionMode::Cell);
+
+        assert!(!sel.contains(1, 4));
+        assert!(sel.contains(1, 5));
+        assert!(sel.contains(1, 100));
+        assert!(sel.contains(2, 0));
+        assert!(sel.contains(3, 2));
+        assert!(!sel.contains(3, 3));
+        assert!(!sel.contains(0, 5));
+        assert!(!sel.contains(4, 0));
+    }
+
+    #[test]
+    fn contains_block_selection() {
+        let sel = selection((3, 2), (1, 5), SelectionMode::Block);
+
+        assert!(sel.contains(2, 2));
+        assert!(sel.contains(2, 5));
+        assert!(!sel.contains(2, 1));
+        assert!(!sel.contains(2, 6));
+        assert!(!sel.contains(4, 3));
+    }
+
+    #[test]
+    fn columns_are_clamped() {
+        let sel = selection((0, 2), (0, 20), SelectionMode::Cell);
+        assert_eq!(sel.columns(0, 10), 2..10);
+        assert_eq!(sel.columns(1, 10), 0..0);
+    }
+
+    #[test]
+    fn text_joins_wrapped_rows() {
+        // "hello worl" is wrapped by terminal, "bar" follows hard newline
+        let term = terminal(10, 3, "hello worldfoo  \r\nbar");
+        let sel = selection((0, 0), (2, 9), SelectionMode::Cell);
+
+        assert_eq!(sel.text(&term), "hello worldfoo\nbar");
+    }
+
+    #[test]
+    fn text_keeps_hard_newline() {
+        let term = terminal(10, 3, "foo   \r\nbar\r\nbaz");
+        let sel = selection((0, 0), (1, 9), SelectionMode::Cell);
+
+        assert_eq!(sel.text(&term), "foo\nbar");
+    }
+
+    #[test]
+    fn block_text_splits_wrapped_rows() {
+        let term = terminal(10, 3, "hello worldfoo");
+        let sel = selection((0, 0), (1, 3), SelectionMode::Block);
+
+        assert_eq!(sel.text(&term), "hell\ndfoo");
+    }
+}
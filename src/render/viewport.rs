@@ -1,5 +1,10 @@
+use std::num::NonZeroU32;
+
+use futures_executor::block_on;
+
 pub struct Viewport {
-    surface: wgpu::Surface,
+    /// `None` when it's headless, there is nothing to present
+    surface: Option<wgpu::Surface>,
     config: wgpu::SurfaceConfiguration,
 }
 
@@ -10,21 +15,49 @@ impl Viewport {
         adapter: &wgpu::Adapter,
         device: &wgpu::Device,
         surface: wgpu::Surface,
+        present_mode: wgpu::PresentMode,
     ) -> Self {
         let render_format = surface
             .get_preferred_format(adapter)
             .unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb);
+        let backend = adapter.get_info().backend;
+        let resolved = resolve_present_mode(backend, present_mode);
+        if resolved != present_mode {
+            log::warn!(
+                "{:?} backend doesn't support {:?}, fall back to {:?}",
+                backend,
+                present_mode,
+                resolved
+            );
+        }
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: render_format,
             width,
             height,
-            present_mode: wgpu::PresentMode::Mailbox,
+            present_mode: resolved,
         };
 
         surface.configure(device, &config);
 
-        Viewport { surface, config }
+        Viewport {
+            surface: Some(surface),
+            config,
+        }
+    }
+
+    /// Viewport without window, grid can be drawn only into [`OffscreenTarget`]
+    pub fn offscreen(width: u32, height: u32) -> Self {
+        Viewport {
+            surface: None,
+            config: wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                width,
+                height,
+                present_mode: wgpu::PresentMode::Fifo,
+            },
+        }
     }
 
     pub fn width(&self) -> u32 {
@@ -39,19 +72,178 @@ impl Viewport {
         self.config.format
     }
 
+    /// Mode which is configured after falling back from unsupported one
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
         self.config.width = width.max(300);
         self.config.height = height.max(200);
-        self.surface.configure(device, &self.config);
+        if let Some(surface) = &self.surface {
+            surface.configure(device, &self.config);
+        }
     }
 
-    pub fn get_current_texture(&mut self) -> Option<wgpu::SurfaceTexture> {
-        match self.surface.get_current_texture() {
+    /// Reconfigure surface and retry once when it's lost or outdated
+    ///
+    /// Returns `None` when nothing can be acquired or it's headless, panics only on out of memory
+    pub fn get_current_texture(&mut self, device: &wgpu::Device) -> Option<wgpu::SurfaceTexture> {
+        let surface = self.surface.as_ref()?;
+        match surface.get_current_texture() {
+            Ok(t) => return Some(t),
+            Err(err @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                log::warn!("Surface error: {}, reconfigure surface", err);
+                surface.configure(device, &self.config);
+            }
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                panic!("Surface error: {}", wgpu::SurfaceError::OutOfMemory);
+            }
+            Err(err) => {
+                log::warn!("Surface error: {}", err);
+                return None;
+            }
+        }
+
+        match surface.get_current_texture() {
             Ok(t) => Some(t),
-            Err(wgpu::SurfaceError::Outdated) => None,
+            Err(wgpu::SurfaceError::OutOfMemory) => {
+                panic!("Surface error: {}", wgpu::SurfaceError::OutOfMemory);
+            }
             Err(err) => {
-                panic!("Surface error: {}", err);
+                log::warn!("Surface error after reconfigure: {}", err);
+                None
             }
         }
     }
 }
+
+/// Texture which grid is rendered to instead of window surface, with buffer to read it back
+pub struct OffscreenTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    /// rows of buffer copy should be aligned
+    padded_bytes_per_row: u32,
+}
+
+impl OffscreenTarget {
+    /// Same size and format as `viewport`, so MSAA texture can be resolved into it
+    pub fn new(device: &wgpu::Device, viewport: &Viewport) -> Self {
+        let (width, height) = (viewport.width(), viewport.height());
+        let format = viewport.format();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen texture"),
+            format,
+            dimension: wgpu::TextureDimension::D2,
+            sample_count: 1,
+            mip_level_count: 1,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (width * 4 + align - 1) & !(align - 1);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            texture,
+            view,
+            buffer,
+            format,
+            width,
+            height,
+            padded_bytes_per_row,
+        }
+    }
+
+    /// Whether it can be still used with `viewport`, it should be created again after resize
+    pub fn fits(&self, viewport: &Viewport) -> bool {
+        (self.width, self.height, self.format)
+            == (viewport.width(), viewport.height(), viewport.format())
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Copy rendered texture into buffer, [`OffscreenTarget::read`] reads it after submit
+    pub fn copy_to_buffer(&self, encoder: &mut wgpu::CommandEncoder) {
+        let size = wgpu::Extent3d {
+            width: self.width,
+            height: self.height,
+            depth_or_array_layers: 1,
+        };
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_bytes_per_row),
+                    rows_per_image: NonZeroU32::new(self.height),
+                },
+            },
+            size,
+        );
+    }
+
+    /// Wait for copied buffer and convert it into RGBA image
+    pub fn read(&self, device: &wgpu::Device) -> image::RgbaImage {
+        let slice = self.buffer.slice(..);
+        let map = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(map).expect("Failed to map offscreen buffer");
+
+        let bgra = matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let row_bytes = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+        {
+            for pixel in row[..row_bytes].chunks_exact(4) {
+                if bgra {
+                    pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+                } else {
+                    pixels.extend_from_slice(pixel);
+                }
+            }
+        }
+        self.buffer.unmap();
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap()
+    }
+}
+
+/// Present modes wgpu can use on `backend`, [`wgpu::PresentMode::Fifo`] is always supported
+///
+/// wgpu doesn't expose surface capabilities and silently falls back to `Fifo`, so this mirrors
+/// backends of wgpu-hal. Vulkan reports modes of driver, so requested one is trusted there.
+fn resolve_present_mode(backend: wgpu::Backend, mode: wgpu::PresentMode) -> wgpu::PresentMode {
+    use wgpu::{Backend, PresentMode};
+
+    match (backend, mode) {
+        (Backend::Vulkan, mode) => mode,
+        // DXGI tearing and CAMetalLayer without display sync
+        (Backend::Dx12 | Backend::Metal, PresentMode::Immediate) => PresentMode::Immediate,
+        _ => PresentMode::Fifo,
+    }
+}
@@ -2,6 +2,15 @@ struct WindowSizeUniform {
     size: vec2<f32>;
     cell_size: vec2<f32>;
     column: u32;
+    // width and height of font atlas layer
+    texture_width: f32;
+    text_contrast: f32;
+    // surface blends in linear space
+    srgb: u32;
+    // first column drawn at left edge
+    column_offset: u32;
+    // px between window edge and grid
+    padding: f32;
 };
 
 struct UiUniform {
@@ -15,15 +24,23 @@ struct UiUniform {
     scrollbar_bg: vec4<f32>;
     // ndc
     scrollbar_top: f32;
-    pad: vec3<f32>;
+    // 0: block, 1: bar, 2: underline
+    cursor_shape: u32;
+    // draw outline instead of filled cursor
+    cursor_hollow: u32;
+    // blink phase
+    cursor_visible: u32;
+    // blink phase of text with blink attribute
+    text_visible: u32;
 };
 
 [[group(0), binding(0)]] var<uniform> window_size: WindowSizeUniform;
 [[group(0), binding(1)]] var<uniform> ui: UiUniform;
 [[group(0), binding(5)]] var font_texture: texture_2d_array<f32>;
 [[group(0), binding(6)]] var font_sampler: sampler;
-
-let TEXTURE_WIDTH: f32 = 1024.0;
+[[group(0), binding(7)]] var color_font_texture: texture_2d_array<f32>;
+[[group(1), binding(0)]] var image_texture: texture_2d<f32>;
+[[group(1), binding(1)]] var image_sampler: sampler;
 
 struct CellInput {
     [[builtin(vertex_index)]] vertex_index: u32;
@@ -37,6 +54,15 @@ struct CellOutput {
     [[location(0)]] color: vec4<f32>;
 };
 
+struct RectInput {
+    [[builtin(vertex_index)]] vertex_index: u32;
+    [[location(0)]] color: vec4<f32>;
+    // px
+    [[location(1)]] position: vec2<f32>;
+    // px
+    [[location(2)]] size: vec2<f32>;
+};
+
 struct TextInput {
     [[builtin(vertex_index)]] vertex_index: u32;
     [[location(0)]] position: vec2<f32>;
@@ -44,6 +70,8 @@ struct TextInput {
     [[location(2)]] tex_size: vec2<f32>;
     [[location(3)]] color: vec3<f32>;
     [[location(4)]] layer: i32;
+    [[location(5)]] is_color: u32;
+    [[location(6)]] blink: u32;
 };
 
 struct TextOutput {
@@ -51,6 +79,20 @@ struct TextOutput {
     [[location(0)]] tex_position: vec2<f32>;
     [[location(1)]] color: vec3<f32>;
     [[location(2)]] layer: i32;
+    [[location(3)]] is_color: u32;
+};
+
+struct ImageInput {
+    [[builtin(vertex_index)]] vertex_index: u32;
+    // px
+    [[location(0)]] position: vec2<f32>;
+    // px
+    [[location(1)]] size: vec2<f32>;
+};
+
+struct ImageOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] tex_position: vec2<f32>;
 };
 
 struct Rect {
@@ -68,6 +110,13 @@ fn pixel_to_ndc(px: vec2<f32>) -> vec2<f32> {
     return vec2<f32>(norm.x - 1.0, 1.0 - norm.y);
 }
 
+// grid is shifted left by panned columns and inset by padding
+fn grid_to_ndc(px: vec2<f32>) -> vec2<f32> {
+    let offset = f32(window_size.column_offset) * window_size.cell_size.x;
+    let padding = window_size.padding;
+    return pixel_to_ndc(px - vec2<f32>(offset - padding, -padding));
+}
+
 fn pixel_x_to_ndc(x: f32) -> f32 {
     let norm = x * 2.0 / window_size.size.x;
     return norm - 1.0;
@@ -86,7 +135,7 @@ fn pixel_width_to_ndc(width: f32) -> f32 {
 fn calculate_cell_rect(cell_pos: vec2<f32>) -> Rect {
     let begin = (cell_pos * window_size.cell_size);
 
-    return Rect(pixel_to_ndc(begin), pixel_size_to_ndc(window_size.cell_size));
+    return Rect(grid_to_ndc(begin), pixel_size_to_ndc(window_size.cell_size));
 }
 
 fn get_rect_position(rect: Rect, vertex_index: u32) -> vec2<f32> {
@@ -133,6 +182,14 @@ fn cell_fs(in: CellOutput) -> [[location(0)]] vec4<f32> {
     return in.color;
 }
 
+[[stage(vertex)]]
+fn rect_vs(
+    model: RectInput,
+) -> CellOutput {
+    let rect = Rect(grid_to_ndc(model.position), pixel_size_to_ndc(model.size));
+    return CellOutput(vec4<f32>(get_rect_position(rect, model.vertex_index), 1.0, 1.0), model.color);
+}
+
 fn calculate_text_pos(line_no: f32, position: vec2<f32>) -> vec2<f32> {
     let pixel_pos = vec2<f32>(0.0, (line_no + 1.0) * window_size.cell_size.y) + vec2<f32>(position.x, -position.y);
     return pixel_to_ndc(pixel_pos);
@@ -142,24 +199,64 @@ fn calculate_text_pos(line_no: f32, position: vec2<f32>) -> vec2<f32> {
 fn text_vs(
     model: TextInput,
 ) -> TextOutput {
-    let rect = Rect(pixel_to_ndc(model.position), pixel_size_to_ndc(model.tex_size));
-    let tex_rect = Rect(model.tex_position / TEXTURE_WIDTH, model.tex_size / TEXTURE_WIDTH);
+    // hidden blinking glyph collapses to empty rect
+    let visible = model.blink == 0u || ui.text_visible != 0u;
+    let size = select(vec2<f32>(0.0), pixel_size_to_ndc(model.tex_size), visible);
+    let rect = Rect(grid_to_ndc(model.position), size);
+    let tex_rect = Rect(model.tex_position / window_size.texture_width, model.tex_size / window_size.texture_width);
     let pos = get_rect_position(rect, model.vertex_index);
     let tex_pos = get_rect_position(tex_rect, model.vertex_index);
     let color = model.color;
     // let color = colorful_color(model.vertex_index).rgb;
-    return TextOutput(vec4<f32>(pos, 1.0, 1.0), tex_pos, color, model.layer);
+    return TextOutput(vec4<f32>(pos, 1.0, 1.0), tex_pos, color, model.layer, model.is_color);
+}
+
+fn enhance_contrast(alpha: f32, k: f32) -> f32 {
+    return alpha * (k + 1.0) / (alpha * k + 1.0);
+}
+
+// Coverage is meant to be blended in sRGB space, sRGB surface blends in linear space
+// which makes light text bold and dark text thin. Adjust coverage so linear blend of
+// text color against black or white matches sRGB blend, weighted by text luminance.
+fn gamma_correct_alpha(alpha: f32, color: vec3<f32>) -> f32 {
+    let luma = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let light = pow(alpha, 2.2);
+    let dark = 1.0 - pow(1.0 - alpha, 2.2);
+    return mix(dark, light, luma);
 }
 
 [[stage(fragment)]]
 fn text_fs(in: TextOutput) -> [[location(0)]] vec4<f32> {
     // return vec4<f32>(in.color, 1.0);
-    let alpha = textureSample(font_texture, font_sampler, in.tex_position, in.layer).r;
+    var alpha = textureSample(font_texture, font_sampler, in.tex_position, in.layer).r;
+    alpha = enhance_contrast(alpha, window_size.text_contrast);
+    if (window_size.srgb != 0u) {
+        alpha = gamma_correct_alpha(alpha, in.color);
+    }
     // if (alpha < 0.02) {
     //     discard;
     // }
     let color = vec4<f32>(in.color, alpha);
-    return color;
+    // color glyph keeps its own color
+    let color_glyph = textureSample(color_font_texture, font_sampler, in.tex_position, in.layer);
+    return select(color, color_glyph, in.is_color != 0u);
+}
+
+[[stage(vertex)]]
+fn image_vs(
+    model: ImageInput,
+) -> ImageOutput {
+    let rect = Rect(grid_to_ndc(model.position), pixel_size_to_ndc(model.size));
+    // whole texture is stretched over rect
+    let tex_rect = Rect(vec2<f32>(0.0), vec2<f32>(1.0));
+    let pos = get_rect_position(rect, model.vertex_index);
+    let tex_pos = get_rect_position(tex_rect, model.vertex_index);
+    return ImageOutput(vec4<f32>(pos, 1.0, 1.0), tex_pos);
+}
+
+[[stage(fragment)]]
+fn image_fs(in: ImageOutput) -> [[location(0)]] vec4<f32> {
+    return textureSample(image_texture, image_sampler, in.tex_position);
 }
 
 [[stage(vertex)]]
@@ -170,7 +267,27 @@ fn ui_vs(
     switch (ui_index) {
         // cursor
         case 0: {
-            let rect = Rect(pixel_to_ndc(ui.cursor_pos * window_size.cell_size), pixel_size_to_ndc(window_size.cell_size));
+            if (ui.cursor_visible == 0u || ui.cursor_hollow != 0u) {
+                return CellOutput(vec4<f32>(0.0), vec4<f32>(0.0));
+            }
+
+            var cursor_begin = ui.cursor_pos * window_size.cell_size;
+            var cursor_size = window_size.cell_size;
+
+            switch (ui.cursor_shape) {
+                // bar
+                case 1: {
+                    cursor_size.x = max(window_size.cell_size.x / 8.0, 1.0);
+                }
+                // underline
+                case 2: {
+                    cursor_size.y = max(window_size.cell_size.y / 8.0, 1.0);
+                    cursor_begin.y = cursor_begin.y + window_size.cell_size.y - cursor_size.y;
+                }
+                default: {}
+            }
+
+            let rect = Rect(grid_to_ndc(cursor_begin), pixel_size_to_ndc(cursor_size));
             let pos = get_rect_position(rect, vertex_index);
 
             return CellOutput(vec4<f32>(pos, 1.0, 1.0), ui.cursor_color);
@@ -197,6 +314,38 @@ fn ui_vs(
             return CellOutput(vec4<f32>(pos, 1.0, 1.0), ui.scrollbar_fg);
             // return CellOutput(vec4<f32>(pos, 1.0, 1.0), vec4<f32>(1.0, 0.0, 0.0, 1.0));
         }
+        // hollow cursor top, bottom, left, right
+        case 3, 4, 5, 6: {
+            if (ui.cursor_visible == 0u || ui.cursor_hollow == 0u) {
+                return CellOutput(vec4<f32>(0.0), vec4<f32>(0.0));
+            }
+
+            let thickness = max(window_size.cell_size.x / 8.0, 1.0);
+            var edge_begin = ui.cursor_pos * window_size.cell_size;
+            var edge_size = window_size.cell_size;
+
+            switch (ui_index) {
+                case 3: {
+                    edge_size.y = thickness;
+                }
+                case 4: {
+                    edge_begin.y = edge_begin.y + window_size.cell_size.y - thickness;
+                    edge_size.y = thickness;
+                }
+                case 5: {
+                    edge_size.x = thickness;
+                }
+                default: {
+                    edge_begin.x = edge_begin.x + window_size.cell_size.x - thickness;
+                    edge_size.x = thickness;
+                }
+            }
+
+            let rect = Rect(grid_to_ndc(edge_begin), pixel_size_to_ndc(edge_size));
+            let pos = get_rect_position(rect, vertex_index);
+
+            return CellOutput(vec4<f32>(pos, 1.0, 1.0), ui.cursor_color);
+        }
         default: {
             // Unknown
             return CellOutput(vec4<f32>(0.0), vec4<f32>(0.0));
@@ -1,45 +1,135 @@
+use std::path::PathBuf;
+
 use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+use serde::Deserialize;
 use termwiz::color::RgbColor;
 use wezterm_term::{color::ColorPalette, TerminalConfiguration};
 
 #[derive(Clone, Debug)]
-pub struct TerminalConfig;
+pub struct TerminalConfig {
+    /// Lines kept in scrollback, older lines are evicted
+    pub scrollback_size: usize,
+    palette: ColorPalette,
+}
 
-impl TerminalConfiguration for TerminalConfig {
-    fn color_palette(&self) -> ColorPalette {
-        ColorPalette {
-            background: RgbColor::new_f32(
-                crate::DEFAULT_BG[0],
-                crate::DEFAULT_BG[1],
-                crate::DEFAULT_BG[2],
-            ),
+impl TerminalConfig {
+    pub fn new(
+        background: [f32; 4],
+        cursor_color: [f32; 4],
+        scrollback_size: usize,
+        theme: Option<&Theme>,
+    ) -> Self {
+        let mut palette = ColorPalette {
+            background: RgbColor::new_f32(background[0], background[1], background[2]),
+            // applications can change it with OSC 12
+            cursor_bg: RgbColor::new_f32(cursor_color[0], cursor_color[1], cursor_color[2]),
             foreground: RgbColor::new_f32(
                 crate::DEFAULT_TEXT[0],
                 crate::DEFAULT_TEXT[1],
                 crate::DEFAULT_TEXT[2],
             ),
             ..Default::default()
+        };
+        if let Some(theme) = theme {
+            theme.apply(&mut palette);
+        }
+
+        Self {
+            scrollback_size,
+            palette,
         }
     }
 }
 
-pub fn start_pty() -> (Box<dyn MasterPty + Send>, Box<dyn Child + Send + Sync>) {
+impl TerminalConfiguration for TerminalConfig {
+    fn scrollback_size(&self) -> usize {
+        self.scrollback_size
+    }
+
+    fn color_palette(&self) -> ColorPalette {
+        self.palette.clone()
+    }
+}
+
+/// Colors written as `#rrggbb`, missing ones keep default palette
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    /// Indexed colors, first 16 are ANSI colors and up to 256 are used
+    pub colors: Vec<String>,
+}
+
+impl Theme {
+    /// Background in 0.0 ~ 1.0 rgb, window is cleared with it
+    pub fn background_color(&self) -> Option<[f32; 3]> {
+        let (r, g, b, _) = parse_color(self.background.as_deref()?)?.to_tuple_rgba();
+        Some([r, g, b])
+    }
+
+    fn apply(&self, palette: &mut ColorPalette) {
+        if let Some(fg) = self.foreground.as_deref().and_then(parse_color) {
+            palette.foreground = fg;
+        }
+        if let Some(bg) = self.background.as_deref().and_then(parse_color) {
+            palette.background = bg;
+        }
+        for (slot, color) in palette.colors.0.iter_mut().zip(self.colors.iter()) {
+            if let Some(color) = parse_color(color) {
+                *slot = color;
+            }
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<RgbColor> {
+    let rgb = RgbColor::from_rgb_str(color);
+    if rgb.is_none() {
+        log::warn!("Invalid theme color: {}", color);
+    }
+    rgb
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PtyConfig {
+    /// Default to `$SHELL` on unix, `powershell` on windows
+    pub shell: Option<String>,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+}
+
+pub fn start_pty(
+    config: &PtyConfig,
+    (cols, rows): (usize, usize),
+) -> (Box<dyn MasterPty + Send>, Box<dyn Child + Send + Sync>) {
     let pty = native_pty_system();
 
     let pair = pty
         .openpty(PtySize {
-            cols: crate::COLUMN as _,
-            rows: crate::ROW as _,
+            cols: cols as _,
+            rows: rows as _,
             pixel_width: 0,
             pixel_height: 0,
         })
         .unwrap();
 
-    #[cfg(unix)]
-    let shell = std::env::var("SHELL").unwrap();
-    #[cfg(windows)]
-    let shell = "powershell";
-    let cmd = CommandBuilder::new(shell);
+    let shell = match config.shell {
+        Some(ref shell) => shell.clone(),
+        #[cfg(unix)]
+        None => std::env::var("SHELL").unwrap(),
+        #[cfg(windows)]
+        None => "powershell".into(),
+    };
+    let mut cmd = CommandBuilder::new(shell);
+    cmd.args(&config.args);
+    if let Some(ref cwd) = config.cwd {
+        cmd.cwd(cwd);
+    }
+    for (key, value) in config.env.iter() {
+        cmd.env(key, value);
+    }
     let child = pair.slave.spawn_command(cmd).unwrap();
 
     (pair.master, child)
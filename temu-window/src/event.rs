@@ -1,11 +1,107 @@
+use std::path::PathBuf;
+
 pub enum TemuEvent {
-    Resize { width: u32, height: u32 },
-    CursorMove { x: f32, y: f32 },
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    CursorMove {
+        x: f32,
+        y: f32,
+    },
     Left(bool),
+    Middle(bool),
+    Focused(bool),
+    /// Window moved to monitor with different DPI
+    ScaleFactor(f32),
+    /// Resize window to fit grid, renderer converts it to `RequestSize` with its cell size
+    ResizeGrid {
+        cols: u32,
+        rows: u32,
+    },
 
     Redraw,
     Close,
     ScrollUp,
     ScrollDown,
+    /// Precise scroll from trackpad, positive value scrolls up
+    ScrollPixels(f32),
+    ScrollPageUp,
+    ScrollPageDown,
+    /// Jump to oldest line of scrollback
+    ScrollToTop,
+    ScrollToBottom,
+    ScrollLock,
+    Copy,
+    Paste(String),
+    /// Empty pattern clears search
+    Search(String),
+    SearchNext,
+    SearchPrev,
+    /// Save current grid as PNG to path, `None` saves it in current directory
+    Screenshot(Option<PathBuf>),
+    /// Show or hide FPS and redraw time
+    ToggleStats,
+    /// Change font size by points, Ctrl+Shift with +/-
+    FontSizeDelta(f32),
+    /// Restore default font size, Ctrl+Shift+0
+    FontSizeReset,
+    /// Replace regular font with font file, fallback fonts are kept
+    ReloadFont(PathBuf),
+
     Char(char),
+    /// Key which doesn't produce `Char` or is pressed with modifiers
+    Key {
+        key: Key,
+        mods: Modifiers,
+    },
+
+    /// Current modifiers, sent whenever they are changed
+    Modifiers(Modifiers),
+
+    /// Render to window
+    SetTitle(String),
+    /// Set inner size of window in physical pixels
+    RequestSize {
+        width: u32,
+        height: u32,
+    },
+    /// Show hand cursor while link is hovered
+    HoverLink(bool),
+    /// Notify user with audible bell
+    Bell,
+    /// Renderer is done and shell is reaped, close window
+    Exit,
+}
+
+/// Platform neutral keycode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    /// Lowercase character key
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// F1 ~ F12
+    Function(u8),
+}
+
+bitflags::bitflags! {
+    pub struct Modifiers: u8 {
+        const SHIFT = 1 << 0;
+        const CTRL = 1 << 1;
+        const ALT = 1 << 2;
+        const SUPER = 1 << 3;
+    }
 }
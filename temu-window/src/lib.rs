@@ -1,22 +1,49 @@
 mod event;
+#[cfg(feature = "test-util")]
+mod mock;
 mod platform;
 
-pub use self::event::TemuEvent;
+pub use self::event::{Key, Modifiers, TemuEvent};
+#[cfg(feature = "test-util")]
+pub use self::mock::{MockHandle, MockWindow};
 pub use crossbeam_channel;
 
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, Sender};
+
+/// Options applied when window is created
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowConfig {
+    /// Initial and minimum inner size in logical pixels
+    pub width: f32,
+    pub height: f32,
+    pub min_width: f32,
+    pub min_height: f32,
+    /// Let compositor show through background with alpha
+    pub transparent: bool,
+    pub always_on_top: bool,
+}
 
 pub trait TemuWindow {
     type Handle: raw_window_handle::HasRawWindowHandle + Send;
 
     fn get_raw_event_handle(&self) -> Self::Handle;
-    fn init(event_tx: Sender<event::TemuEvent>) -> Self;
+    fn init(
+        event_tx: Sender<event::TemuEvent>,
+        window_rx: Receiver<event::TemuEvent>,
+        config: WindowConfig,
+    ) -> Self;
     fn size(&self) -> (u32, u32);
     fn scale_factor(&self) -> f32;
+    /// Resize window to fit `cols` x `rows` grid once renderer handles it
+    fn request_resize(&self, cols: u32, rows: u32);
     fn run(self);
 }
 
 #[profiling::function]
-pub fn init_native_window(event_tx: Sender<event::TemuEvent>) -> impl TemuWindow {
-    self::platform::NativeWindow::init(event_tx)
+pub fn init_native_window(
+    event_tx: Sender<event::TemuEvent>,
+    window_rx: Receiver<event::TemuEvent>,
+    config: WindowConfig,
+) -> impl TemuWindow {
+    self::platform::NativeWindow::init(event_tx, window_rx, config)
 }
@@ -0,0 +1,81 @@
+use crossbeam_channel::{Receiver, Sender};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, WebHandle};
+
+use crate::{TemuEvent, TemuWindow, WindowConfig};
+
+/// Window without platform backend, events are pushed by hand
+pub struct MockWindow {
+    event_tx: Sender<TemuEvent>,
+    window_rx: Receiver<TemuEvent>,
+    size: (u32, u32),
+    scale_factor: f32,
+}
+
+impl MockWindow {
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.size = (width, height);
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    /// Push event as if it came from window
+    pub fn send(&self, event: TemuEvent) {
+        self.event_tx.send(event).ok();
+    }
+
+    /// Sender for pushing events from other threads
+    pub fn event_sender(&self) -> Sender<TemuEvent> {
+        self.event_tx.clone()
+    }
+
+    /// Events sent to window like `SetTitle`
+    pub fn window_events(&self) -> Receiver<TemuEvent> {
+        self.window_rx.clone()
+    }
+}
+
+/// Empty handle, it can't be used to create surface so renderer should run headless
+pub struct MockHandle;
+
+unsafe impl HasRawWindowHandle for MockHandle {
+    fn raw_window_handle(&self) -> RawWindowHandle {
+        RawWindowHandle::Web(WebHandle::empty())
+    }
+}
+
+impl TemuWindow for MockWindow {
+    type Handle = MockHandle;
+
+    fn get_raw_event_handle(&self) -> Self::Handle {
+        MockHandle
+    }
+
+    fn init(
+        event_tx: Sender<TemuEvent>,
+        window_rx: Receiver<TemuEvent>,
+        config: WindowConfig,
+    ) -> Self {
+        Self {
+            event_tx,
+            window_rx,
+            size: (config.width.ceil() as u32, config.height.ceil() as u32),
+            scale_factor: 1.0,
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    fn request_resize(&self, cols: u32, rows: u32) {
+        self.send(TemuEvent::ResizeGrid { cols, rows });
+    }
+
+    fn run(self) {}
+}
@@ -1,25 +1,32 @@
-use crate::{TemuEvent, TemuPtyEvent, TemuWindow};
+use crate::{Key, Modifiers, TemuEvent, TemuWindow, WindowConfig};
 
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, Sender};
 use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, PSTR, RECT, SIZE, WPARAM};
-use windows::Win32::Graphics::Gdi::ValidateRect;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, PWSTR, RECT, SIZE, WPARAM};
+use windows::Win32::Graphics::Gdi::{BeginPaint, EndPaint, PAINTSTRUCT};
 use windows::Win32::System::{
     Com::{CoInitializeEx, COINIT_MULTITHREADED},
-    LibraryLoader::GetModuleHandleA,
+    LibraryLoader::GetModuleHandleW,
 };
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetKeyState, ReleaseCapture, SetCapture};
+// unicode variants so WM_CHAR carries UTF-16 instead of ANSI code page
 use windows::Win32::UI::WindowsAndMessaging::{
-    CreateWindowExA, DefWindowProcA, DestroyWindow, DispatchMessageA, GetClientRect, GetMessageA,
-    GetWindowLongPtrA, LoadCursorW, PostQuitMessage, RegisterClassA, SetWindowLongPtrA,
-    TranslateMessage, CREATESTRUCTA, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, GWLP_USERDATA,
-    IDC_ARROW, MSG, WM_CLOSE, WM_DESTROY, WM_GETMINMAXINFO, WM_NCCREATE, WM_PAINT, WM_SIZE,
-    WNDCLASSA, WS_OVERLAPPEDWINDOW, WS_VISIBLE,
+    AdjustWindowRect, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW,
+    FlashWindowEx, GetClientRect, GetMessageW, GetWindowLongPtrW, LoadCursorW, MessageBeep,
+    PostMessageW, PostQuitMessage, RegisterClassW, SetWindowLongPtrW, SetWindowPos, SetWindowTextW,
+    TranslateMessage, CREATESTRUCTW, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, FLASHWINFO,
+    FLASHW_TIMERNOFG, FLASHW_TRAY, GWLP_USERDATA, IDC_ARROW, MB_OK, MINMAXINFO, MSG, SWP_NOMOVE,
+    SWP_NOZORDER, WM_CHAR, WM_CLOSE, WM_DESTROY, WM_DPICHANGED, WM_GETMINMAXINFO, WM_KEYDOWN,
+    WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP,
+    WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_NCCREATE, WM_PAINT, WM_SETFOCUS, WM_SIZE, WM_SYSCHAR,
+    WM_SYSKEYDOWN, WM_SYSKEYUP, WNDCLASSW, WS_EX_TOPMOST, WS_OVERLAPPEDWINDOW, WS_VISIBLE,
 };
 
 use raw_window_handle::{windows::WindowsHandle, HasRawWindowHandle, RawWindowHandle};
 pub struct Window {
     handle: WindowsHandle,
+    event_tx: Sender<TemuEvent>,
 }
 
 unsafe impl HasRawWindowHandle for Window {
@@ -30,45 +37,68 @@ unsafe impl HasRawWindowHandle for Window {
 
 struct WindowContext {
     event_tx: Sender<TemuEvent>,
-    #[allow(dead_code)]
-    pty_event_tx: Sender<TemuPtyEvent>,
+    /// outer size of window
+    min_size: POINT,
+    /// first half of character out of BMP, WM_CHAR sends UTF-16 code unit
+    high_surrogate: Option<u16>,
+    clipboard: Option<arboard::Clipboard>,
 }
 
 impl TemuWindow for Window {
-    fn init(event_tx: Sender<crate::event::TemuEvent>, pty_event_tx: Sender<TemuPtyEvent>) -> Self {
+    fn init(
+        event_tx: Sender<TemuEvent>,
+        window_rx: Receiver<TemuEvent>,
+        config: WindowConfig,
+    ) -> Self {
+        let (width, height) = outer_size(config.width, config.height);
+        let (min_width, min_height) = outer_size(config.min_width, config.min_height);
         let ctx = WindowContext {
-            event_tx,
-            pty_event_tx,
+            event_tx: event_tx.clone(),
+            min_size: POINT {
+                x: min_width,
+                y: min_height,
+            },
+            high_surrogate: None,
+            clipboard: arboard::Clipboard::new()
+                .map_err(|err| log::error!("Failed to open clipboard: {}", err))
+                .ok(),
         };
         let lparam = Box::leak(Box::new(ctx)) as *mut WindowContext;
 
         let mut handle = WindowsHandle::empty();
         unsafe {
             CoInitializeEx(ptr::null_mut(), COINIT_MULTITHREADED).unwrap();
-            let instance = GetModuleHandleA(None);
+            let instance = GetModuleHandleW(None);
             debug_assert!(instance.0 != 0);
 
-            let wc = WNDCLASSA {
+            let class_name = to_wide("temu");
+            let wc = WNDCLASSW {
                 hCursor: LoadCursorW(None, IDC_ARROW),
                 hInstance: instance,
-                lpszClassName: PSTR(b"temu\0".as_ptr() as _),
+                lpszClassName: PWSTR(class_name.as_ptr() as _),
                 style: CS_HREDRAW | CS_VREDRAW,
                 lpfnWndProc: Some(wndproc),
                 ..Default::default()
             };
 
-            let atom = RegisterClassA(&wc);
+            let atom = RegisterClassW(&wc);
             debug_assert!(atom != 0);
 
-            let hwnd = CreateWindowExA(
-                Default::default(),
-                "temu",
-                "Temu",
+            let title = to_wide("Temu");
+            let ex_style = if config.always_on_top {
+                WS_EX_TOPMOST
+            } else {
+                Default::default()
+            };
+            let hwnd = CreateWindowExW(
+                ex_style,
+                PWSTR(class_name.as_ptr() as _),
+                PWSTR(title.as_ptr() as _),
                 WS_OVERLAPPEDWINDOW | WS_VISIBLE,
                 CW_USEDEFAULT,
                 CW_USEDEFAULT,
-                800,
-                500,
+                width,
+                height,
                 None,
                 None,
                 instance,
@@ -77,18 +107,64 @@ impl TemuWindow for Window {
 
             handle.hwnd = hwnd.0 as _;
             handle.hinstance = instance.0 as _;
+
+            std::thread::spawn(move || {
+                for event in window_rx {
+                    match event {
+                        TemuEvent::SetTitle(title) => {
+                            let title = to_wide(&title);
+                            SetWindowTextW(hwnd, PWSTR(title.as_ptr() as _));
+                        }
+                        TemuEvent::RequestSize { width, height } => {
+                            let (width, height) = outer_size(width as f32, height as f32);
+                            SetWindowPos(
+                                hwnd,
+                                None,
+                                0,
+                                0,
+                                width,
+                                height,
+                                SWP_NOMOVE | SWP_NOZORDER,
+                            );
+                        }
+                        TemuEvent::Bell => {
+                            MessageBeep(MB_OK);
+                            // flash taskbar button until window is focused
+                            let info = FLASHWINFO {
+                                cbSize: std::mem::size_of::<FLASHWINFO>() as u32,
+                                hwnd,
+                                dwFlags: FLASHW_TRAY | FLASHW_TIMERNOFG,
+                                uCount: 0,
+                                dwTimeout: 0,
+                            };
+                            FlashWindowEx(&info);
+                        }
+                        // window must be destroyed by its own thread
+                        TemuEvent::Exit => {
+                            PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0));
+                        }
+                        _ => {}
+                    }
+                }
+            });
         }
 
-        Self { handle }
+        Self { handle, event_tx }
+    }
+
+    fn request_resize(&self, cols: u32, rows: u32) {
+        self.event_tx
+            .send(TemuEvent::ResizeGrid { cols, rows })
+            .ok();
     }
 
     fn run(self) {
         let mut message = MSG::default();
 
         unsafe {
-            while GetMessageA(&mut message, HWND(0), 0, 0).into() {
+            while GetMessageW(&mut message, HWND(0), 0, 0).into() {
                 TranslateMessage(&message);
-                DispatchMessageA(&mut message);
+                DispatchMessageW(&mut message);
                 if CLOSED.load(Ordering::Acquire) {
                     return;
                 }
@@ -98,32 +174,41 @@ impl TemuWindow for Window {
 }
 
 static CLOSED: AtomicBool = AtomicBool::new(false);
+const WHEEL_DELTA: u16 = 120;
 
 extern "system" fn wndproc(hwnd: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     unsafe {
         let ctx: &'static mut WindowContext;
 
         if matches!(message, WM_GETMINMAXINFO) {
+            // it's sent before WM_NCCREATE too, when context is not stored yet
+            let ctx = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const WindowContext;
+            if let Some(ctx) = ctx.as_ref() {
+                (*(lparam.0 as *mut MINMAXINFO)).ptMinTrackSize = ctx.min_size;
+            }
             return LRESULT(0);
         }
 
         if message == WM_NCCREATE {
-            let st = lparam.0 as *mut CREATESTRUCTA;
+            let st = lparam.0 as *mut CREATESTRUCTW;
             ctx = (*st)
                 .lpCreateParams
                 .cast::<WindowContext>()
                 .as_mut()
                 .unwrap();
-            SetWindowLongPtrA(hwnd, GWLP_USERDATA, ctx as *mut WindowContext as isize);
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, ctx as *mut WindowContext as isize);
         } else {
-            ctx = (GetWindowLongPtrA(hwnd, GWLP_USERDATA) as *mut WindowContext)
+            ctx = (GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut WindowContext)
                 .as_mut()
                 .unwrap();
         }
 
         match message {
             WM_PAINT => {
-                // ValidateRect(hwnd, std::ptr::null());
+                // validate update region, otherwise WM_PAINT is sent again right away
+                let mut ps = PAINTSTRUCT::default();
+                BeginPaint(hwnd, &mut ps);
+                EndPaint(hwnd, &ps);
                 ctx.event_tx.send(TemuEvent::Redraw).ok();
             }
             WM_SIZE => {
@@ -135,6 +220,125 @@ extern "system" fn wndproc(hwnd: HWND, message: u32, wparam: WPARAM, lparam: LPA
                     })
                     .ok();
             }
+            WM_KEYDOWN | WM_SYSKEYDOWN => {
+                let vk = wparam.0 as u32;
+                let mods = get_modifiers();
+                if is_modifier_key(vk) {
+                    ctx.event_tx.send(TemuEvent::Modifiers(mods)).ok();
+                    return DefWindowProcW(hwnd, message, wparam, lparam);
+                }
+                let key = match convert_key(vk) {
+                    Some(key) => key,
+                    None => return DefWindowProcW(hwnd, message, wparam, lparam),
+                };
+                // Ctrl+Insert and Shift+Insert are copy and paste
+                if key == Key::Insert && mods == Modifiers::CTRL {
+                    ctx.event_tx.send(TemuEvent::Copy).ok();
+                    return LRESULT(0);
+                }
+                if key == Key::Insert && mods == Modifiers::SHIFT {
+                    if let Some(text) = ctx.clipboard.as_mut().and_then(|c| c.get_text().ok()) {
+                        ctx.event_tx.send(TemuEvent::Paste(text)).ok();
+                    }
+                    return LRESULT(0);
+                }
+                // plain character keys are delivered by WM_CHAR
+                let has_char = matches!(
+                    key,
+                    Key::Char(_) | Key::Enter | Key::Tab | Key::Backspace | Key::Escape
+                );
+                // AltGr is reported as Ctrl+Alt, its character comes with WM_CHAR too
+                let alt_gr = mods.contains(Modifiers::CTRL | Modifiers::ALT);
+                if has_char && (!mods.intersects(Modifiers::CTRL | Modifiers::ALT) || alt_gr) {
+                    return DefWindowProcW(hwnd, message, wparam, lparam);
+                }
+                ctx.event_tx.send(TemuEvent::Key { key, mods }).ok();
+            }
+            WM_KEYUP | WM_SYSKEYUP => {
+                if is_modifier_key(wparam.0 as u32) {
+                    ctx.event_tx
+                        .send(TemuEvent::Modifiers(get_modifiers()))
+                        .ok();
+                }
+                return DefWindowProcW(hwnd, message, wparam, lparam);
+            }
+            WM_CHAR => {
+                let unit = wparam.0 as u16;
+                let mods = get_modifiers();
+                // control character of Ctrl+key, it's already sent by WM_KEYDOWN
+                let control = unit < 0x20 || unit == 0x7F;
+                if control && mods.contains(Modifiers::CTRL) && !mods.contains(Modifiers::ALT) {
+                    return LRESULT(0);
+                }
+                match unit {
+                    0xD800..=0xDBFF => ctx.high_surrogate = Some(unit),
+                    0xDC00..=0xDFFF => {
+                        if let Some(high) = ctx.high_surrogate.take() {
+                            for c in char::decode_utf16([high, unit]).flatten() {
+                                ctx.event_tx.send(TemuEvent::Char(c)).ok();
+                            }
+                        }
+                    }
+                    _ => {
+                        ctx.high_surrogate = None;
+                        if let Some(c) = char::from_u32(unit as u32) {
+                            ctx.event_tx.send(TemuEvent::Char(c)).ok();
+                        }
+                    }
+                }
+            }
+            // Alt+key is already sent by WM_SYSKEYDOWN, don't beep
+            WM_SYSCHAR => {}
+            WM_MOUSEMOVE => {
+                // client coordinates, negative while captured outside of window
+                let x = (lparam.0 & 0xFFFF) as i16;
+                let y = ((lparam.0 >> 16) & 0xFFFF) as i16;
+                ctx.event_tx
+                    .send(TemuEvent::CursorMove {
+                        x: x as f32,
+                        y: y as f32,
+                    })
+                    .ok();
+            }
+            WM_LBUTTONDOWN => {
+                // keep dragging selection outside of window
+                SetCapture(hwnd);
+                ctx.event_tx.send(TemuEvent::Left(true)).ok();
+            }
+            WM_LBUTTONUP => {
+                ReleaseCapture();
+                ctx.event_tx.send(TemuEvent::Left(false)).ok();
+            }
+            WM_MBUTTONDOWN => {
+                ctx.event_tx.send(TemuEvent::Middle(true)).ok();
+            }
+            WM_MBUTTONUP => {
+                ctx.event_tx.send(TemuEvent::Middle(false)).ok();
+            }
+            WM_MOUSEWHEEL => {
+                // high word of wparam is wheel delta, WHEEL_DELTA per notch
+                let delta = ((wparam.0 >> 16) & 0xFFFF) as i16;
+                let notches = (delta.unsigned_abs() / WHEEL_DELTA).max(1);
+                for _ in 0..notches {
+                    let event = if delta > 0 {
+                        TemuEvent::ScrollUp
+                    } else {
+                        TemuEvent::ScrollDown
+                    };
+                    ctx.event_tx.send(event).ok();
+                }
+            }
+            WM_DPICHANGED => {
+                // low word of wparam is new dpi
+                let dpi = (wparam.0 & 0xFFFF) as f32;
+                ctx.event_tx.send(TemuEvent::ScaleFactor(dpi / 96.0)).ok();
+            }
+            WM_SETFOCUS => {
+                ctx.event_tx.send(TemuEvent::Focused(true)).ok();
+            }
+            WM_KILLFOCUS => {
+                ctx.event_tx.send(TemuEvent::Focused(false)).ok();
+            }
             WM_DESTROY => {
                 log::info!("WM_DESTROY");
                 PostQuitMessage(0);
@@ -145,12 +349,26 @@ extern "system" fn wndproc(hwnd: HWND, message: u32, wparam: WPARAM, lparam: LPA
                 ctx.event_tx.send(TemuEvent::Close).ok();
                 DestroyWindow(hwnd);
             }
-            _ => return DefWindowProcA(hwnd, message, wparam, lparam),
+            _ => return DefWindowProcW(hwnd, message, wparam, lparam),
         }
         LRESULT(0)
     }
 }
 
+/// Outer size of window which has client area of `width` x `height`
+fn outer_size(width: f32, height: f32) -> (i32, i32) {
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: width.ceil() as i32,
+        bottom: height.ceil() as i32,
+    };
+    unsafe {
+        AdjustWindowRect(&mut rect, WS_OVERLAPPEDWINDOW, false);
+    }
+    (rect.right - rect.left, rect.bottom - rect.top)
+}
+
 unsafe fn get_window_size(hwnd: HWND) -> SIZE {
     let mut client_rect = RECT::default();
     GetClientRect(hwnd, &mut client_rect);
@@ -159,3 +377,53 @@ unsafe fn get_window_size(hwnd: HWND) -> SIZE {
         cy: client_rect.bottom - client_rect.top,
     }
 }
+
+/// Null terminated UTF-16 string
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(Some(0)).collect()
+}
+
+/// VK_SHIFT, VK_CONTROL, VK_MENU, VK_LWIN, VK_RWIN
+fn is_modifier_key(vk: u32) -> bool {
+    matches!(vk, 0x10..=0x12 | 0x5B | 0x5C)
+}
+
+unsafe fn get_modifiers() -> Modifiers {
+    // high bit is set when key is down
+    let pressed = |vk: i32| GetKeyState(vk) < 0;
+    let mut mods = Modifiers::empty();
+    mods.set(Modifiers::SHIFT, pressed(0x10));
+    mods.set(Modifiers::CTRL, pressed(0x11));
+    mods.set(Modifiers::ALT, pressed(0x12));
+    mods.set(Modifiers::SUPER, pressed(0x5B) || pressed(0x5C));
+    mods
+}
+
+fn convert_key(vk: u32) -> Option<Key> {
+    let key = match vk {
+        0x08 => Key::Backspace,
+        0x09 => Key::Tab,
+        0x0D => Key::Enter,
+        0x1B => Key::Escape,
+        0x20 => Key::Char(' '),
+        0x21 => Key::PageUp,
+        0x22 => Key::PageDown,
+        0x23 => Key::End,
+        0x24 => Key::Home,
+        0x25 => Key::Left,
+        0x26 => Key::Up,
+        0x27 => Key::Right,
+        0x28 => Key::Down,
+        0x2D => Key::Insert,
+        0x2E => Key::Delete,
+        // '0' ~ '9'
+        0x30..=0x39 => Key::Char(char::from(vk as u8)),
+        // 'A' ~ 'Z'
+        0x41..=0x5A => Key::Char(char::from(vk as u8).to_ascii_lowercase()),
+        // VK_F1 ~ VK_F12
+        0x70..=0x7B => Key::Function((vk - 0x70 + 1) as u8),
+        _ => return None,
+    };
+
+    Some(key)
+}
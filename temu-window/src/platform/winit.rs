@@ -1,15 +1,18 @@
-use crossbeam_channel::Sender;
+use crossbeam_channel::{Receiver, Sender};
 use raw_window_handle::HasRawWindowHandle;
-use winit::dpi::LogicalSize;
-use winit::event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+    VirtualKeyCode, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{CursorIcon, UserAttentionType, Window, WindowBuilder};
 
-use crate::TemuEvent;
+use crate::{Key, Modifiers, TemuEvent, WindowConfig};
 
 pub struct WinitWindow {
     inner: Window,
-    event_loop: EventLoop<()>,
+    event_loop: EventLoop<TemuEvent>,
     event_tx: Sender<TemuEvent>,
 }
 
@@ -34,14 +37,29 @@ impl crate::TemuWindow for WinitWindow {
         }
     }
 
-    fn init(event_tx: Sender<TemuEvent>) -> Self {
-        let event_loop = EventLoop::new();
+    fn init(
+        event_tx: Sender<TemuEvent>,
+        window_rx: Receiver<TemuEvent>,
+        config: WindowConfig,
+    ) -> Self {
+        let event_loop = EventLoop::with_user_event();
+        let proxy = event_loop.create_proxy();
+
+        // wake up event loop when render thread send event
+        std::thread::spawn(move || {
+            for event in window_rx {
+                if proxy.send_event(event).is_err() {
+                    break;
+                }
+            }
+        });
+
         let inner = WindowBuilder::new()
-            .with_inner_size(LogicalSize::new(720u32, 400u32))
+            .with_inner_size(LogicalSize::new(config.width, config.height))
+            .with_min_inner_size(LogicalSize::new(config.min_width, config.min_height))
             .with_title("Temu")
-            .with_transparent(true)
-            // // for debug purpose
-            // .with_always_on_top(true)
+            .with_transparent(config.transparent)
+            .with_always_on_top(config.always_on_top)
             .build(&event_loop)
             .unwrap();
 
@@ -61,23 +79,59 @@ impl crate::TemuWindow for WinitWindow {
         self.inner.scale_factor() as f32
     }
 
+    fn request_resize(&self, cols: u32, rows: u32) {
+        self.event_tx
+            .send(TemuEvent::ResizeGrid { cols, rows })
+            .ok();
+    }
+
     #[profiling::function]
     fn run(self) {
         let Self {
-            inner: _,
+            inner,
             event_loop,
             event_tx,
         } = self;
 
+        let mut clipboard = arboard::Clipboard::new()
+            .map_err(|err| log::error!("Failed to open clipboard: {}", err))
+            .ok();
+        let mut modifiers = ModifiersState::empty();
+        // skip ReceivedCharacter of handled shortcut
+        let mut suppress_char = false;
+
         event_loop.run(move |e, _target, flow| match e {
             Event::DeviceEvent { .. } => *flow = ControlFlow::Wait,
             Event::RedrawRequested(_) => {
                 event_tx.send(TemuEvent::Redraw).ok();
             }
+            Event::UserEvent(TemuEvent::SetTitle(title)) => {
+                inner.set_title(&title);
+            }
+            Event::UserEvent(TemuEvent::RequestSize { width, height }) => {
+                inner.set_inner_size(PhysicalSize::new(width, height));
+            }
+            Event::UserEvent(TemuEvent::HoverLink(hover)) => {
+                inner.set_cursor_icon(if hover {
+                    CursorIcon::Hand
+                } else {
+                    CursorIcon::Default
+                });
+            }
+            Event::UserEvent(TemuEvent::Exit) => {
+                *flow = ControlFlow::Exit;
+            }
+            Event::UserEvent(TemuEvent::Bell) => {
+                // winit can't beep, let window manager request attention instead
+                inner.request_user_attention(Some(UserAttentionType::Informational));
+            }
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => {
-                    event_tx.send(TemuEvent::Close).ok();
-                    *flow = ControlFlow::Exit;
+                    // exit after renderer reaps shell, or now if it's already gone
+                    let renderer_gone = event_tx.send(TemuEvent::Close).is_err();
+                    if renderer_gone {
+                        *flow = ControlFlow::Exit;
+                    }
                 }
                 WindowEvent::Resized(size) => {
                     event_tx
@@ -87,8 +141,231 @@ impl crate::TemuWindow for WinitWindow {
                         })
                         .ok();
                 }
+                WindowEvent::ScaleFactorChanged {
+                    scale_factor,
+                    new_inner_size,
+                } => {
+                    event_tx
+                        .send(TemuEvent::ScaleFactor(scale_factor as f32))
+                        .ok();
+                    event_tx
+                        .send(TemuEvent::Resize {
+                            width: new_inner_size.width,
+                            height: new_inner_size.height,
+                        })
+                        .ok();
+                }
+                WindowEvent::Focused(focused) => {
+                    event_tx.send(TemuEvent::Focused(focused)).ok();
+                }
                 WindowEvent::ReceivedCharacter(c) => {
-                    event_tx.send(TemuEvent::Char(c)).ok();
+                    if suppress_char {
+                        suppress_char = false;
+                    } else {
+                        event_tx.send(TemuEvent::Char(c)).ok();
+                    }
+                }
+                WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state;
+                    event_tx
+                        .send(TemuEvent::Modifiers(convert_modifiers(state)))
+                        .ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Scroll),
+                            ..
+                        },
+                    ..
+                } => {
+                    event_tx.send(TemuEvent::ScrollLock).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::C),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    suppress_char = true;
+                    event_tx.send(TemuEvent::Copy).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::V),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    suppress_char = true;
+                    if let Some(text) = clipboard.as_mut().and_then(|c| c.get_text().ok()) {
+                        event_tx.send(TemuEvent::Paste(text)).ok();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Insert),
+                            ..
+                        },
+                    ..
+                } if modifiers == ModifiersState::CTRL => {
+                    event_tx.send(TemuEvent::Copy).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::Insert),
+                            ..
+                        },
+                    ..
+                } if modifiers == ModifiersState::SHIFT => {
+                    if let Some(text) = clipboard.as_mut().and_then(|c| c.get_text().ok()) {
+                        event_tx.send(TemuEvent::Paste(text)).ok();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    // search clipboard text
+                    suppress_char = true;
+                    let pattern = clipboard
+                        .as_mut()
+                        .and_then(|c| c.get_text().ok())
+                        .unwrap_or_default();
+                    event_tx.send(TemuEvent::Search(pattern)).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::S),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    suppress_char = true;
+                    event_tx.send(TemuEvent::Screenshot(None)).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::D),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    suppress_char = true;
+                    event_tx.send(TemuEvent::ToggleStats).ok();
+                }
+                // plain Ctrl+- and Ctrl+0 belong to applications like emacs
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode:
+                                Some(
+                                    code @ (VirtualKeyCode::Equals
+                                    | VirtualKeyCode::Plus
+                                    | VirtualKeyCode::NumpadAdd
+                                    | VirtualKeyCode::Minus
+                                    | VirtualKeyCode::NumpadSubtract
+                                    | VirtualKeyCode::Key0
+                                    | VirtualKeyCode::Numpad0),
+                                ),
+                            ..
+                        },
+                    ..
+                } if modifiers.ctrl() && modifiers.shift() => {
+                    suppress_char = true;
+                    let event = match code {
+                        VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                            TemuEvent::FontSizeDelta(-1.0)
+                        }
+                        VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => TemuEvent::FontSizeReset,
+                        _ => TemuEvent::FontSizeDelta(1.0),
+                    };
+                    event_tx.send(event).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode:
+                                Some(
+                                    code @ (VirtualKeyCode::PageUp
+                                    | VirtualKeyCode::PageDown
+                                    | VirtualKeyCode::Home
+                                    | VirtualKeyCode::End),
+                                ),
+                            ..
+                        },
+                    ..
+                } if modifiers.shift() && !modifiers.ctrl() && !modifiers.alt() => {
+                    let event = match code {
+                        VirtualKeyCode::PageUp => TemuEvent::ScrollPageUp,
+                        VirtualKeyCode::PageDown => TemuEvent::ScrollPageDown,
+                        VirtualKeyCode::Home => TemuEvent::ScrollToTop,
+                        _ => TemuEvent::ScrollToBottom,
+                    };
+                    event_tx.send(event).ok();
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F3),
+                            ..
+                        },
+                    ..
+                } => {
+                    if modifiers.shift() {
+                        event_tx.send(TemuEvent::SearchPrev).ok();
+                    } else {
+                        event_tx.send(TemuEvent::SearchNext).ok();
+                    }
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(code),
+                            ..
+                        },
+                    ..
+                } => {
+                    let mods = convert_modifiers(modifiers);
+                    let key = match convert_key(code) {
+                        Some(key) => key,
+                        None => return,
+                    };
+                    // plain character keys are delivered by ReceivedCharacter
+                    let has_char = matches!(
+                        key,
+                        Key::Char(_) | Key::Enter | Key::Tab | Key::Backspace | Key::Escape
+                    );
+                    if has_char && !mods.intersects(Modifiers::CTRL | Modifiers::ALT) {
+                        return;
+                    }
+                    if has_char || key == Key::Delete {
+                        suppress_char = true;
+                    }
+                    event_tx.send(TemuEvent::Key { key, mods }).ok();
                 }
                 WindowEvent::MouseInput {
                     button: MouseButton::Left,
@@ -99,6 +376,15 @@ impl crate::TemuWindow for WinitWindow {
                         .send(TemuEvent::Left(state == ElementState::Pressed))
                         .ok();
                 }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Middle,
+                    state,
+                    ..
+                } => {
+                    event_tx
+                        .send(TemuEvent::Middle(state == ElementState::Pressed))
+                        .ok();
+                }
                 WindowEvent::CursorMoved { position, .. } => {
                     event_tx
                         .send(TemuEvent::CursorMove {
@@ -116,7 +402,7 @@ impl crate::TemuWindow for WinitWindow {
                         }
                     }
                     MouseScrollDelta::PixelDelta(p) => {
-                        log::info!("{:?}", p);
+                        event_tx.send(TemuEvent::ScrollPixels(p.y as f32)).ok();
                     }
                 },
                 _ => {}
@@ -125,3 +411,85 @@ impl crate::TemuWindow for WinitWindow {
         });
     }
 }
+
+fn convert_modifiers(state: ModifiersState) -> Modifiers {
+    let mut mods = Modifiers::empty();
+    mods.set(Modifiers::SHIFT, state.shift());
+    mods.set(Modifiers::CTRL, state.ctrl());
+    mods.set(Modifiers::ALT, state.alt());
+    mods.set(Modifiers::SUPER, state.logo());
+    mods
+}
+
+fn convert_key(code: VirtualKeyCode) -> Option<Key> {
+    use VirtualKeyCode as V;
+
+    let key = match code {
+        V::Key1 => Key::Char('1'),
+        V::Key2 => Key::Char('2'),
+        V::Key3 => Key::Char('3'),
+        V::Key4 => Key::Char('4'),
+        V::Key5 => Key::Char('5'),
+        V::Key6 => Key::Char('6'),
+        V::Key7 => Key::Char('7'),
+        V::Key8 => Key::Char('8'),
+        V::Key9 => Key::Char('9'),
+        V::Key0 => Key::Char('0'),
+        V::A => Key::Char('a'),
+        V::B => Key::Char('b'),
+        V::C => Key::Char('c'),
+        V::D => Key::Char('d'),
+        V::E => Key::Char('e'),
+        V::F => Key::Char('f'),
+        V::G => Key::Char('g'),
+        V::H => Key::Char('h'),
+        V::I => Key::Char('i'),
+        V::J => Key::Char('j'),
+        V::K => Key::Char('k'),
+        V::L => Key::Char('l'),
+        V::M => Key::Char('m'),
+        V::N => Key::Char('n'),
+        V::O => Key::Char('o'),
+        V::P => Key::Char('p'),
+        V::Q => Key::Char('q'),
+        V::R => Key::Char('r'),
+        V::S => Key::Char('s'),
+        V::T => Key::Char('t'),
+        V::U => Key::Char('u'),
+        V::V => Key::Char('v'),
+        V::W => Key::Char('w'),
+        V::X => Key::Char('x'),
+        V::Y => Key::Char('y'),
+        V::Z => Key::Char('z'),
+        V::Space => Key::Char(' '),
+        V::Return | V::NumpadEnter => Key::Enter,
+        V::Tab => Key::Tab,
+        V::Back => Key::Backspace,
+        V::Escape => Key::Escape,
+        V::Up => Key::Up,
+        V::Down => Key::Down,
+        V::Left => Key::Left,
+        V::Right => Key::Right,
+        V::Home => Key::Home,
+        V::End => Key::End,
+        V::PageUp => Key::PageUp,
+        V::PageDown => Key::PageDown,
+        V::Insert => Key::Insert,
+        V::Delete => Key::Delete,
+        V::F1 => Key::Function(1),
+        V::F2 => Key::Function(2),
+        V::F3 => Key::Function(3),
+        V::F4 => Key::Function(4),
+        V::F5 => Key::Function(5),
+        V::F6 => Key::Function(6),
+        V::F7 => Key::Function(7),
+        V::F8 => Key::Function(8),
+        V::F9 => Key::Function(9),
+        V::F10 => Key::Function(10),
+        V::F11 => Key::Function(11),
+        V::F12 => Key::Function(12),
+        _ => return None,
+    };
+
+    Some(key)
+}
@@ -0,0 +1,198 @@
+//! Drive renderer with `MockWindow` and check what it draws, without display
+#![cfg(unix)]
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use temu::{AppConfig, PtyConfig, ScrollbarPolicy, TemuApp, TemuEvent, TemuHandle};
+use temu_window::{crossbeam_channel, MockWindow, TemuWindow};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Channel difference allowed for antialiased edges, which differ between adapters
+const TOLERANCE: i16 = 8;
+
+struct Headless {
+    handle: TemuHandle,
+    window: MockWindow,
+}
+
+impl Headless {
+    /// Run `script` with `sh`, `None` when machine has no adapter at all
+    fn start(script: &str) -> Option<Self> {
+        let mut config = AppConfig {
+            pty: PtyConfig {
+                shell: Some("sh".into()),
+                args: vec!["-c".into(), script.into()],
+                ..Default::default()
+            },
+            columns: 20,
+            rows: 4,
+            ..Default::default()
+        };
+        // screenshot shouldn't depend on timing, and only text is drawn in foreground color
+        config.render.cursor_blink = false;
+        config.render.cursor_color = config.render.background;
+        config.render.scrollbar_policy = ScrollbarPolicy::Never;
+
+        let app = TemuApp::new(config);
+        let (event_tx, event_rx) = crossbeam_channel::unbounded();
+        let (window_tx, window_rx) = crossbeam_channel::unbounded();
+        let window = MockWindow::init(event_tx.clone(), window_rx, app.window_config());
+        let handle = app.spawn_headless(
+            window.size(),
+            window.scale_factor(),
+            event_tx,
+            event_rx,
+            window_tx,
+        );
+
+        // renderer gives up right away without adapter
+        let no_adapter = window
+            .window_events()
+            .recv_timeout(Duration::from_millis(500))
+            .map_or(false, |event| {
+                matches!(event, TemuEvent::SetTitle(title) if title.contains("No supported GPU"))
+            });
+        if no_adapter {
+            eprintln!("No adapter, skip");
+            handle.join();
+            return None;
+        }
+
+        Some(Self { handle, window })
+    }
+
+    /// Take screenshots until `done` accepts one
+    fn wait_screenshot(
+        &self,
+        path: &Path,
+        mut done: impl FnMut(&image::RgbaImage) -> bool,
+    ) -> image::RgbaImage {
+        let start = Instant::now();
+        loop {
+            std::fs::remove_file(path).ok();
+            self.handle.screenshot(path);
+
+            // renderer saves it asynchronously
+            let saved = loop {
+                if let Ok(image) = image::open(path) {
+                    break Some(image.into_rgba8());
+                }
+                if start.elapsed() > TIMEOUT {
+                    break None;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            };
+
+            match saved {
+                Some(image) if done(&image) => return image,
+                Some(image) if start.elapsed() > TIMEOUT => return image,
+                Some(_) => std::thread::sleep(Duration::from_millis(100)),
+                None => panic!("Screenshot isn't saved to {}", path.display()),
+            }
+        }
+    }
+
+    fn stop(self) {
+        self.window.send(TemuEvent::Close);
+        self.handle.join();
+    }
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("temu-{}-{}.png", name, std::process::id()))
+}
+
+/// Default text is white on black background
+fn has_text(image: &image::RgbaImage) -> bool {
+    image.pixels().any(|pixel| pixel[0] > 128)
+}
+
+/// Compare with `tests/golden/<name>.png`
+///
+/// `TEMU_BLESS=1` writes it from `image`, for new test or after intended change
+fn assert_golden(name: &str, image: &image::RgbaImage) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.png", name));
+    if std::env::var_os("TEMU_BLESS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        eprintln!("Golden image is written to {}", path.display());
+        return;
+    }
+    assert!(
+        path.exists(),
+        "{} is missing, run with TEMU_BLESS=1 to write it",
+        path.display()
+    );
+
+    let golden = image::open(&path).unwrap().into_rgba8();
+    assert_eq!(
+        golden.dimensions(),
+        image.dimensions(),
+        "Size differs from {}",
+        name
+    );
+    let differs = golden
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(golden, pixel)| {
+            golden
+                .0
+                .iter()
+                .zip(pixel.0.iter())
+                .any(|(&a, &b)| (a as i16 - b as i16).abs() > TOLERANCE)
+        })
+        .count();
+    assert_eq!(
+        differs,
+        0,
+        "{} pixels differ from {}",
+        differs,
+        path.display()
+    );
+}
+
+#[test]
+fn draws_shell_output() {
+    let headless = match Headless::start("printf hello; sleep 10") {
+        Some(headless) => headless,
+        None => return,
+    };
+    let path = temp_path("output");
+
+    let image = headless.wait_screenshot(&path, has_text);
+    std::fs::remove_file(&path).ok();
+    headless.stop();
+
+    assert!(has_text(&image), "Nothing is drawn");
+    // "hello" takes 5 of 20 columns in first row
+    assert!(image
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel[0] > 128)
+        .all(|(x, y, _)| x < image.width() / 2 && y < image.height() / 2));
+}
+
+#[test]
+fn matches_golden_image() {
+    let headless = match Headless::start("printf 'temu\\n\\033[31mred\\033[0m'; sleep 10") {
+        Some(headless) => headless,
+        None => return,
+    };
+    let path = temp_path("golden");
+
+    // second line is written in red
+    let image = headless.wait_screenshot(&path, |image| {
+        image
+            .pixels()
+            .any(|pixel| pixel[0] > 128 && pixel[0] / 2 > pixel[1])
+    });
+    std::fs::remove_file(&path).ok();
+    headless.stop();
+
+    assert_golden("colored_lines", &image);
+}
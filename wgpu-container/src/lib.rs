@@ -4,3 +4,53 @@ mod wgpu_cell;
 mod wgpu_vec;
 
 pub use crate::{wgpu_cell::WgpuCell, wgpu_vec::WgpuVec};
+
+/// Tests which need real gpu, run with `cargo test --features wgpu-test`
+#[cfg(all(test, feature = "wgpu-test"))]
+mod test_util {
+    /// Device of any adapter
+    ///
+    /// # Panics
+    ///
+    /// Panics when machine has no adapter at all
+    pub fn device() -> (wgpu::Device, wgpu::Queue) {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = futures_executor::block_on(
+            instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
+        )
+        .expect("No adapter for wgpu-test");
+
+        futures_executor::block_on(adapter.request_device(&Default::default(), None))
+            .expect("Failed to request device")
+    }
+
+    /// Read first `len` elements of `buffer` which has [`wgpu::BufferUsages::COPY_SRC`]
+    pub fn read_buffer<T: bytemuck::Pod>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        len: usize,
+    ) -> Vec<T> {
+        let size = (len * std::mem::size_of::<T>()) as u64;
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let map = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        futures_executor::block_on(map).expect("Failed to map staging buffer");
+
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+
+        data
+    }
+}
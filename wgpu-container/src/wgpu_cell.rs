@@ -1,23 +1,26 @@
-use bytemuck::{cast_slice, Pod};
-use std::{ops::Deref, slice::from_ref};
+use bytemuck::{bytes_of_mut, cast_slice, Pod};
+use std::{mem::size_of, ops::Deref, slice::from_ref};
 use wgpu::util::DeviceExt;
 
 /// Wrapper around `T`
 pub struct WgpuCell<T> {
     value: T,
     inner: wgpu::Buffer,
+    usage: wgpu::BufferUsages,
     outdated: bool,
 }
 
 impl<T: Pod> WgpuCell<T> {
     /// Create new [`WgpuCell`] with usage and value it will automatically add [`wgpu::BufferUsages::COPY_DST`]
     pub fn new(device: &wgpu::Device, usage: wgpu::BufferUsages, value: T) -> Self {
+        let usage = usage | wgpu::BufferUsages::COPY_DST;
         Self {
             inner: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 contents: cast_slice(from_ref(&value)),
                 label: None,
-                usage: usage | wgpu::BufferUsages::COPY_DST,
+                usage,
             }),
+            usage,
             value,
             outdated: false,
         }
@@ -47,6 +50,14 @@ impl<T: Pod> WgpuCell<T> {
         f(&mut self.value)
     }
 
+    /// Update inner value and write it to gpu-buffer immediately
+    pub fn update_now<R>(&mut self, queue: &wgpu::Queue, f: impl FnOnce(&mut T) -> R) -> R {
+        let ret = f(&mut self.value);
+        self.outdated = true;
+        self.flush(queue);
+        ret
+    }
+
     /// Write value to gpu-buffer
     ///
     /// If buffer is up to date, it won't do write
@@ -57,6 +68,47 @@ impl<T: Pod> WgpuCell<T> {
 
         self.outdated = false;
     }
+
+    /// Read value back from gpu-buffer
+    ///
+    /// It copies gpu-buffer into staging buffer and waits until it's mapped,
+    /// pending [`WgpuCell::flush`] is not applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics when cell is created without [`wgpu::BufferUsages::COPY_SRC`] or staging buffer can't be mapped
+    pub async fn map_read(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> T {
+        assert!(
+            self.usage.contains(wgpu::BufferUsages::COPY_SRC),
+            "WgpuCell needs COPY_SRC usage to be read"
+        );
+
+        // buffer_init already padded gpu-buffer to COPY_BUFFER_ALIGNMENT
+        let align = wgpu::COPY_BUFFER_ALIGNMENT;
+        let size = (size_of::<T>() as u64 + align - 1) & !(align - 1);
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.inner, 0, &staging, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let map = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        map.await.expect("Failed to map staging buffer");
+
+        let mut value = T::zeroed();
+        bytes_of_mut(&mut value).copy_from_slice(&slice.get_mapped_range()[..size_of::<T>()]);
+        staging.unmap();
+
+        value
+    }
 }
 
 impl<T> Deref for WgpuCell<T> {
@@ -66,3 +118,54 @@ impl<T> Deref for WgpuCell<T> {
         &self.value
     }
 }
+
+#[cfg(all(test, feature = "wgpu-test"))]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use bytemuck::Zeroable;
+    use futures_executor::block_on;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+    struct Uniform {
+        color: [f32; 4],
+        pos: [f32; 2],
+        width: f32,
+        visible: u32,
+    }
+
+    #[test]
+    fn map_read_round_trips_struct() {
+        let (device, queue) = test_util::device();
+        let value = Uniform {
+            color: [0.1, 0.2, 0.3, 1.0],
+            pos: [4.0, 5.0],
+            width: 6.5,
+            visible: 1,
+        };
+        let usage = wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC;
+
+        let mut cell = WgpuCell::new(&device, usage, value);
+        assert_eq!(block_on(cell.map_read(&device, &queue)), value);
+
+        cell.update_now(&queue, |value| value.pos = [7.0, 8.0]);
+        assert_eq!(block_on(cell.map_read(&device, &queue)).pos, [7.0, 8.0]);
+
+        // not flushed yet
+        cell.update(|value| value.visible = 0);
+        assert_eq!(block_on(cell.map_read(&device, &queue)).visible, 1);
+
+        cell.flush(&queue);
+        assert_eq!(block_on(cell.map_read(&device, &queue)), *cell);
+    }
+
+    #[test]
+    #[should_panic(expected = "COPY_SRC")]
+    fn map_read_needs_copy_src() {
+        let (device, queue) = test_util::device();
+        let cell = WgpuCell::<Uniform>::zeroed(&device, wgpu::BufferUsages::UNIFORM);
+
+        block_on(cell.map_read(&device, &queue));
+    }
+}
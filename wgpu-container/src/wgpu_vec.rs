@@ -1,11 +1,26 @@
+use std::ops::Range;
+
 use bytemuck::Pod;
 
+/// Number of consecutive over-allocated writes before automatic shrink
+const SHRINK_AFTER_WRITES: u32 = 16;
+
 /// Wrapper around `Vec<T>`
 pub struct WgpuVec<T> {
     cpu_buffer: Vec<T>,
-    inner: wgpu::Buffer,
+    /// gpu buffers written in turn, so gpu can read previous one while next one is written
+    inner: Vec<wgpu::Buffer>,
+    /// index of buffer which has latest data
+    current: usize,
+    /// span of each gpu buffer which is behind cpu buffer
+    stale: Vec<Option<Range<usize>>>,
     inner_cap: usize,
     usage: wgpu::BufferUsages,
+    /// span of cpu buffer which is changed since last write, end can be past the length
+    dirty: Option<Range<usize>>,
+    auto_shrink: bool,
+    /// consecutive writes which used less than quarter of gpu-buffer
+    underused_writes: u32,
 }
 
 impl<T: Pod> WgpuVec<T> {
@@ -16,24 +31,43 @@ impl<T: Pod> WgpuVec<T> {
     }
 
     /// Create new [`WgpuVec`] with usage and capacity it will automatically add [`wgpu::BufferUsages::COPY_DST`]
+    #[inline]
     pub fn with_capacity(
         device: &wgpu::Device,
         usage: wgpu::BufferUsages,
         capacity: usize,
     ) -> Self {
-        // capacity should be more than zero
+        Self::with_ring(device, usage, capacity, 1)
+    }
+
+    /// Create new [`WgpuVec`] which writes to `buffer_count` gpu buffers in turn
+    ///
+    /// Write doesn't wait for gpu which is still reading previous frame,
+    /// but it takes `buffer_count` times of gpu memory so it's only worth for buffers written every frame.
+    /// [`WgpuVec::gpu_buffer`] should be taken again after each write.
+    pub fn with_ring(
+        device: &wgpu::Device,
+        usage: wgpu::BufferUsages,
+        capacity: usize,
+        buffer_count: usize,
+    ) -> Self {
+        // capacity and buffer count should be more than zero
         let capacity = capacity.max(1);
+        let buffer_count = buffer_count.max(1);
+        let usage = usage | wgpu::BufferUsages::COPY_DST;
 
         Self {
-            inner: device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                mapped_at_creation: false,
-                size: (std::mem::size_of::<T>() * capacity) as u64,
-                usage: usage | wgpu::BufferUsages::COPY_DST,
-            }),
+            inner: (0..buffer_count)
+                .map(|_| create_buffer::<T>(device, usage, capacity))
+                .collect(),
+            current: 0,
+            stale: vec![None; buffer_count],
             inner_cap: capacity,
             cpu_buffer: Vec::with_capacity(capacity),
-            usage: usage | wgpu::BufferUsages::COPY_DST,
+            usage,
+            dirty: None,
+            auto_shrink: false,
+            underused_writes: 0,
         }
     }
 
@@ -43,16 +77,56 @@ impl<T: Pod> WgpuVec<T> {
         self.cpu_buffer.len()
     }
 
+    /// Returns `true` if the cpu buffer contains no elements.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cpu_buffer.is_empty()
+    }
+
     /// Returns the capacity of gpu buffer.
     #[inline]
     pub fn gpu_capacity(&self) -> usize {
         self.inner_cap
     }
 
-    /// Get inner [`wgpu::Buffer`]
+    /// Enable automatic shrink of gpu buffer
+    ///
+    /// When cpu buffer uses less than quarter of gpu buffer for several consecutive writes,
+    /// gpu buffer will be reallocated with half of its capacity, see [`WgpuVec::shrink_to_fit`]
+    #[inline]
+    pub fn set_auto_shrink(&mut self, auto_shrink: bool) {
+        self.auto_shrink = auto_shrink;
+        self.underused_writes = 0;
+    }
+
+    /// Grow both buffers to hold at least `capacity` elements without reallocation
+    ///
+    /// This reallocates gpu buffer so any reference from [`WgpuVec::gpu_buffer`] should be taken again
+    pub fn reserve(&mut self, device: &wgpu::Device, capacity: usize) {
+        self.cpu_buffer
+            .reserve(capacity.saturating_sub(self.cpu_buffer.len()));
+        if capacity > self.inner_cap {
+            self.realloc(device, capacity);
+            // new gpu buffer has nothing yet
+            self.mark_dirty(0..usize::MAX);
+        }
+    }
+
+    /// Shrink gpu buffer to length of cpu buffer and upload it
+    ///
+    /// This reallocates gpu buffer so any reference from [`WgpuVec::gpu_buffer`] should be taken again
+    pub fn shrink_to_fit(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let cap = self.cpu_buffer.len().max(1);
+        if cap < self.inner_cap {
+            self.realloc(device, cap);
+        }
+        self.write(device, queue);
+    }
+
+    /// Get inner [`wgpu::Buffer`] which has latest data
     #[inline]
     pub fn gpu_buffer(&self) -> &wgpu::Buffer {
-        &self.inner
+        &self.inner[self.current]
     }
 
     /// Extracts a slice containing the entire cpu buffer.
@@ -64,27 +138,289 @@ impl<T: Pod> WgpuVec<T> {
     /// Get mutable reference underlying cpu buffer.
     ///
     /// Caller should call [`WgpuVec::write`] later for update gpu buffer
+    ///
+    /// Whole buffer will be marked as dirty
     #[inline]
     pub fn cpu_buffer_mut(&mut self) -> &mut Vec<T> {
+        self.mark_dirty(0..usize::MAX);
         &mut self.cpu_buffer
     }
 
+    /// Iterate mutable references of cpu buffer
+    ///
+    /// Only span between first and last yielded element is marked as dirty,
+    /// so [`WgpuVec::write_dirty`] can upload part of buffer after updating a few elements.
+    /// Since iterator borrows `self`, upload is deferred until it's dropped
+    #[inline]
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> + '_ {
+        let dirty = &mut self.dirty;
+        self.cpu_buffer
+            .iter_mut()
+            .enumerate()
+            .map(move |(index, value)| {
+                *dirty = Some(merge_dirty(dirty.take(), index..index + 1));
+                value
+            })
+    }
+
+    /// Appends an element to the back of cpu buffer.
+    ///
+    /// Caller should call [`WgpuVec::write_dirty`] later for update gpu buffer
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        let len = self.cpu_buffer.len();
+        self.mark_dirty(len..len + 1);
+        self.cpu_buffer.push(value);
+    }
+
+    /// Appends all elements in a slice to the back of cpu buffer.
+    ///
+    /// Caller should call [`WgpuVec::write_dirty`] later for update gpu buffer
+    #[inline]
+    pub fn extend_from_slice(&mut self, data: &[T]) {
+        let len = self.cpu_buffer.len();
+        self.mark_dirty(len..len + data.len());
+        self.cpu_buffer.extend_from_slice(data);
+    }
+
+    /// Removes and returns the element at `index`, shifting all elements after it to the left.
+    ///
+    /// Shifted tail is marked as dirty
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn remove(&mut self, index: usize) -> T {
+        let len = self.cpu_buffer.len();
+        let value = self.cpu_buffer.remove(index);
+        self.mark_dirty(index..len);
+        value
+    }
+
+    /// Removes and returns the element at `index`, last element is moved to its place.
+    ///
+    /// Only `index` is marked as dirty, but order of elements changes
+    /// so it shouldn't be used for instances which depend on draw order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    #[inline]
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        let value = self.cpu_buffer.swap_remove(index);
+        self.mark_dirty(index..index + 1);
+        value
+    }
+
+    /// Clears the cpu buffer, gpu buffer is kept
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Shortens the cpu buffer, gpu buffer is kept
+    ///
+    /// Stale tail of gpu buffer is never drawn so [`WgpuVec::write_dirty`] only uploads elements pushed later
+    ///
+    /// Dirty span past new length is dropped
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        self.cpu_buffer.truncate(len);
+        self.dirty = match self.dirty.take() {
+            Some(Range { start, end }) if start < len => Some(start..end.min(len)),
+            _ => None,
+        };
+    }
+
     /// Write cpu-buffer to gpu-buffer
     ///
     /// It will reuse gpu-buffer when capacity is bigger than cpu-buffer
     pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        if self.inner_cap < self.cpu_buffer.len() {
-            while self.inner_cap < self.cpu_buffer.len() {
-                self.inner_cap *= 2;
+        if !self.reserve_gpu(device) {
+            self.try_auto_shrink(device);
+        }
+        self.dirty = None;
+
+        self.upload(queue, Some(0..usize::MAX));
+    }
+
+    /// Write only changed span of cpu-buffer to gpu-buffer
+    ///
+    /// When gpu-buffer is reallocated, whole cpu-buffer will be uploaded
+    pub fn write_dirty(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let reallocated = self.reserve_gpu(device) || self.try_auto_shrink(device);
+        let changed = self.dirty.take();
+        if !reallocated && changed.is_none() {
+            return;
+        }
+
+        self.upload(queue, changed);
+    }
+
+    /// Move to next gpu buffer and bring it up to date
+    fn upload(&mut self, queue: &wgpu::Queue, changed: Option<Range<usize>>) {
+        if let Some(changed) = changed {
+            for stale in self.stale.iter_mut() {
+                *stale = Some(merge_dirty(stale.take(), changed.clone()));
             }
-            self.inner = device.create_buffer(&wgpu::BufferDescriptor {
-                label: None,
-                usage: self.usage,
-                mapped_at_creation: false,
-                size: (self.inner_cap * std::mem::size_of::<T>()) as u64,
-            });
         }
 
-        queue.write_buffer(&self.inner, 0, bytemuck::cast_slice(&self.cpu_buffer));
+        self.current = (self.current + 1) % self.inner.len();
+
+        if let Some(Range { start, end }) = self.stale[self.current].take() {
+            let end = end.min(self.cpu_buffer.len());
+            if start < end {
+                queue.write_buffer(
+                    &self.inner[self.current],
+                    (start * std::mem::size_of::<T>()) as u64,
+                    bytemuck::cast_slice(&self.cpu_buffer[start..end]),
+                );
+            }
+        }
+    }
+
+    #[inline]
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(merge_dirty(self.dirty.take(), range));
+    }
+
+    /// Grow gpu-buffer to fit cpu-buffer, returns `true` when reallocated
+    fn reserve_gpu(&mut self, device: &wgpu::Device) -> bool {
+        if self.inner_cap >= self.cpu_buffer.len() {
+            return false;
+        }
+
+        let mut cap = self.inner_cap;
+        while cap < self.cpu_buffer.len() {
+            cap *= 2;
+        }
+        self.realloc(device, cap);
+
+        true
+    }
+
+    /// Shrink gpu-buffer when it was over-allocated for a while, returns `true` when reallocated
+    fn try_auto_shrink(&mut self, device: &wgpu::Device) -> bool {
+        if !self.auto_shrink || self.cpu_buffer.len() * 4 >= self.inner_cap {
+            self.underused_writes = 0;
+            return false;
+        }
+
+        self.underused_writes += 1;
+        if self.underused_writes < SHRINK_AFTER_WRITES {
+            return false;
+        }
+
+        self.realloc(device, (self.inner_cap / 2).max(1));
+        true
+    }
+
+    fn realloc(&mut self, device: &wgpu::Device, cap: usize) {
+        self.inner_cap = cap;
+        self.underused_writes = 0;
+        for buffer in self.inner.iter_mut() {
+            *buffer = create_buffer::<T>(device, self.usage, cap);
+        }
+        // new buffers have nothing yet
+        self.stale.fill(Some(0..usize::MAX));
+    }
+}
+
+fn create_buffer<T>(device: &wgpu::Device, usage: wgpu::BufferUsages, cap: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        usage,
+        mapped_at_creation: false,
+        size: (cap * std::mem::size_of::<T>()) as u64,
+    })
+}
+
+#[inline]
+fn merge_dirty(prev: Option<Range<usize>>, range: Range<usize>) -> Range<usize> {
+    match prev {
+        Some(prev) => prev.start.min(range.start)..prev.end.max(range.end),
+        None => range,
+    }
+}
+
+#[cfg(all(test, feature = "wgpu-test"))]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    fn filled(device: &wgpu::Device, queue: &wgpu::Queue) -> WgpuVec<u32> {
+        let mut vec = WgpuVec::with_capacity(device, wgpu::BufferUsages::COPY_SRC, 8);
+        vec.extend_from_slice(&[0, 1, 2, 3, 4]);
+        vec.write_dirty(device, queue);
+        assert_eq!(vec.dirty, None);
+        vec
+    }
+
+    fn gpu_contents(device: &wgpu::Device, queue: &wgpu::Queue, vec: &WgpuVec<u32>) -> Vec<u32> {
+        test_util::read_buffer(device, queue, vec.gpu_buffer(), vec.len())
+    }
+
+    #[test]
+    fn remove_marks_shifted_tail() {
+        let (device, queue) = test_util::device();
+        let mut vec = filled(&device, &queue);
+
+        assert_eq!(vec.remove(1), 1);
+        assert_eq!(vec.cpu_buffer(), [0, 2, 3, 4]);
+        // old last slot is included but never uploaded
+        assert_eq!(vec.dirty, Some(1..5));
+
+        vec.write_dirty(&device, &queue);
+        assert_eq!(vec.dirty, None);
+        assert_eq!(gpu_contents(&device, &queue, &vec), vec.cpu_buffer());
+    }
+
+    #[test]
+    fn swap_remove_marks_only_index() {
+        let (device, queue) = test_util::device();
+        let mut vec = filled(&device, &queue);
+
+        assert_eq!(vec.swap_remove(1), 1);
+        assert_eq!(vec.cpu_buffer(), [0, 4, 2, 3]);
+        assert_eq!(vec.dirty, Some(1..2));
+
+        vec.write_dirty(&device, &queue);
+        assert_eq!(gpu_contents(&device, &queue, &vec), vec.cpu_buffer());
+
+        // removing last element leaves dirty range past the length
+        assert_eq!(vec.swap_remove(3), 3);
+        assert_eq!(vec.cpu_buffer(), [0, 4, 2]);
+        assert_eq!(vec.dirty, Some(3..4));
+
+        vec.write_dirty(&device, &queue);
+        assert_eq!(gpu_contents(&device, &queue, &vec), vec.cpu_buffer());
+    }
+
+    #[test]
+    fn truncate_clamps_dirty() {
+        let (device, queue) = test_util::device();
+        let mut vec = filled(&device, &queue);
+
+        *vec.iter_mut().nth(1).unwrap() = 10;
+        vec.push(5);
+        assert_eq!(vec.dirty, Some(1..6));
+
+        vec.truncate(3);
+        assert_eq!(vec.cpu_buffer(), [0, 10, 2]);
+        assert_eq!(vec.dirty, Some(1..3));
+
+        vec.write_dirty(&device, &queue);
+        assert_eq!(gpu_contents(&device, &queue, &vec), vec.cpu_buffer());
+
+        // nothing left to upload when whole dirty span is cut off
+        vec.push(3);
+        vec.truncate(3);
+        assert_eq!(vec.dirty, None);
+
+        vec.push(4);
+        vec.clear();
+        assert!(vec.is_empty());
+        assert_eq!(vec.dirty, None);
     }
 }